@@ -1,17 +1,32 @@
 use std::path::PathBuf;
 
-use clap::{Parser, Subcommand};
+use clap::{ArgGroup, Args, Parser, Subcommand};
+use script_herder_core::config::{ConfigTarget, PROFILE_ENV};
+use script_herder_core::config::schema::ValueKind;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
-    /// Sets a custom config file
+    /// Loads a config file instead of the machine config, repeat to stack
+    /// files with later ones taking precedence
     #[arg(short, long, value_name = "FILE")]
-    pub config: Option<PathBuf>,
+    pub config: Vec<PathBuf>,
+
+    /// Keeps loading the machine config below the files given with --config
+    #[arg(long, requires = "config")]
+    pub with_machine_config: bool,
 
     #[arg(short = 't', long = "verbose", help = "Verbose output")]
     pub verbose: bool,
 
+    /// Applies a named profile over the machine config
+    #[arg(long, value_name = "NAME", env = PROFILE_ENV, global = true)]
+    pub profile: Option<String>,
+
+    /// Overrides a config value for this run only
+    #[arg(short = 'o', long = "set", value_name = "KEY=VALUE", global = true)]
+    pub overrides: Vec<String>,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -19,14 +34,106 @@ pub struct Cli {
 #[derive(Subcommand)]
 pub enum Commands {
     /// Get/Set config values
-    Config {
-        #[arg(help = "The key to get/set")]
-        key: Option<String>,
-        #[arg(help = "The value to set")]
-        value: Option<String>,
-        #[arg(short, long, help = "List known config keys")]
-        list: bool,
-    },
+    Config(ConfigArgs),
     /// Get info about repository
     Repo,
 }
+
+#[derive(Args)]
+#[command(group(ArgGroup::new("action").conflicts_with_all(["key", "list", "dump", "history", "undo", "migrate"])))]
+pub struct ConfigArgs {
+    #[arg(help = "The key to get/set")]
+    pub key: Option<String>,
+    #[arg(help = "The value to set")]
+    pub value: Option<String>,
+    #[arg(short, long, help = "List known config keys")]
+    pub list: bool,
+    #[arg(long, conflicts_with = "value", help = "Show the layer each value comes from")]
+    pub show_origin: bool,
+    #[arg(long, conflicts_with_all = ["value", "show_origin"], help = "Remove the key")]
+    pub unset: bool,
+    #[arg(long, requires = "value", conflicts_with_all = ["unset", "show_origin", "remove"], help = "Append the value to an array")]
+    pub add: bool,
+    #[arg(long, requires = "value", conflicts_with_all = ["unset", "show_origin"], help = "Remove the value from an array")]
+    pub remove: bool,
+    #[arg(long, requires = "value", conflicts_with_all = ["add", "remove"], help = "Store the value encrypted")]
+    pub secret: bool,
+    #[arg(long, conflicts_with_all = ["value", "list", "dump"], help = "Show recorded changes, for the key if given")]
+    pub history: bool,
+    #[arg(long, conflicts_with_all = ["key", "list", "dump", "history"], help = "Revert the last recorded change")]
+    pub undo: bool,
+    #[arg(long, conflicts_with_all = ["key", "list", "dump", "history", "undo"], help = "Upgrade config files to the current schema version")]
+    pub migrate: bool,
+    #[arg(long, requires = "migrate", help = "Show the migrations without writing them")]
+    pub dry_run: bool,
+    #[arg(long, conflicts_with_all = ["key", "list"], help = "Print every effective value and its layer")]
+    pub dump: bool,
+    #[arg(long, value_name = "FILE", num_args = 0..=1, group = "action", help = "Write a layer, or the effective config, to a JSON file, prints it when no file is given")]
+    pub export: Option<Option<PathBuf>>,
+    #[arg(long = "layer", value_name = "LAYER", value_parser = parse_target, requires = "export", help = "Export only this layer")]
+    pub export_layer: Option<ConfigTarget>,
+    #[arg(long, value_name = "FILE", group = "action", help = "Load the values of a JSON or TOML file into a layer")]
+    pub import: Option<PathBuf>,
+    #[arg(long, requires = "import", conflicts_with = "replace", help = "Keep the keys of the layer missing from the file (default)")]
+    pub merge: bool,
+    #[arg(long, requires = "import", help = "Remove the keys of the layer missing from the file")]
+    pub replace: bool,
+    #[arg(long, num_args = 2, value_names = ["FROM", "TO"], value_parser = parse_target, group = "action", help = "Show the keys that differ between two layers")]
+    pub diff: Option<Vec<ConfigTarget>>,
+    #[arg(long, group = "kind", help = "Parse the value as JSON, or print --dump as JSON")]
+    pub json: bool,
+    #[arg(long, group = "kind", requires = "value", help = "Parse the value as an integer")]
+    pub int: bool,
+    #[arg(long, group = "kind", requires = "value", help = "Parse the value as a boolean")]
+    pub bool: bool,
+    #[command(flatten)]
+    pub layer: TargetArgs,
+}
+
+#[derive(Args)]
+pub struct TargetArgs {
+    #[arg(long, group = "target", help = "Use the machine config")]
+    pub machine: bool,
+    #[arg(long, group = "target", help = "Use the repository config")]
+    pub repo: bool,
+    #[arg(long, group = "target", help = "Use the config in the current folder")]
+    pub local: bool,
+}
+
+fn parse_target(value: &str) -> Result<ConfigTarget, String> {
+    ConfigTarget::from_str(value)
+        .ok_or_else(|| format!("expected one of: {}", ConfigTarget::list().join(", ")))
+}
+
+impl ConfigArgs {
+    /// Kind the value was asked to be parsed as, if any.
+    pub fn kind(&self) -> Option<ValueKind> {
+        if self.json {
+            Some(ValueKind::Json)
+        } else if self.int {
+            Some(ValueKind::Integer)
+        } else if self.bool {
+            Some(ValueKind::Boolean)
+        } else {
+            None
+        }
+    }
+
+    pub fn target(&self) -> Option<ConfigTarget> {
+        self.layer.target()
+    }
+}
+
+impl TargetArgs {
+    pub fn target(&self) -> Option<ConfigTarget> {
+        if self.machine {
+            Some(ConfigTarget::Machine)
+        } else if self.repo {
+            Some(ConfigTarget::Repo)
+        } else if self.local {
+            Some(ConfigTarget::Local)
+        } else {
+            None
+        }
+    }
+}
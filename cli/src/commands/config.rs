@@ -1,35 +1,538 @@
-use script_herder_core::config::{AppConfig, KnownConfigs};
+use script_herder_core::config::{AppConfig, Config, ConfigTarget, KnownConfigs};
+use script_herder_core::config::config_json::ConfigJson;
+use script_herder_core::config::key_path;
+use script_herder_core::config::provider::ConfigOrigin;
+use script_herder_core::config::history::HistoryEntry;
+use script_herder_core::config::schema;
+use script_herder_core::config::secret;
+use script_herder_core::infra::io::ConfigFile;
+use script_herder_core::infra::wildcard;
+use serde_json::{json, Map, Value};
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
 
-pub fn run_config(mut config: AppConfig, key: String, value: Option<String>, list_known: bool) {
-    if list_known {
-        println!("Known config keys:");
-        for key in KnownConfigs::list() {
-            println!(" - {}", key);
-        }
+use crate::args::ConfigArgs;
+
+pub fn run_config(mut config: AppConfig, args: ConfigArgs) {
+    config.provider.mask_secrets(true);
+
+    if let Some(file) = args.export {
+        export(&config, file, args.export_layer);
+        return;
+    }
+
+    if let Some(file) = args.import {
+        import(&mut config, file, args.replace, args.layer.target());
+        return;
+    }
+
+    if let Some(layers) = args.diff {
+        diff(&config, layers[0], layers[1]);
+        return;
+    }
+
+    if args.list {
+        list_known();
+        list_env(&config);
+        return;
+    }
+
+    if args.history {
+        show_history(&config, args.key.as_deref());
+        return;
+    }
+
+    if args.undo {
+        undo(&config);
+        return;
+    }
+
+    if args.migrate {
+        migrate(&mut config, args.dry_run);
         return;
     }
 
+    if args.dump {
+        dump(&config, args.json);
+        return;
+    }
+
+    let target = args.target();
+    let kind = args.kind();
+    let key = args.key.unwrap_or_default();
     if key.is_empty() {
         println!("No key provided");
         return;
     }
 
-    match value {
-        Some(val) => {
-            config.provider.set(&key, val);
-            let result = config.provider.sync();
-            for r in result {
-                match r {
-                    Ok(_) => continue,
-                    Err(e) => println!("Error: {}", e)
+    if wildcard::is_pattern(&key) {
+        if args.value.is_some() || args.unset {
+            println!("Error: Wildcards can only be used to read values");
+            return;
+        }
+
+        show_matching(&config, &key, target, args.show_origin);
+        return;
+    }
+
+    if args.show_origin {
+        show_origin(&config, &key, target);
+        return;
+    }
+
+    if args.unset {
+        let removed = match target {
+            Some(target) => config.provider.unset_in(target, &key),
+            None => config.provider.unset(&key),
+        };
+
+        // What is left can only come from layers that are never saved.
+        let remaining = config.provider.get_origins(&key).into_iter()
+            .find(|o| target.is_none() || o.target == target);
+        match (removed, remaining) {
+            (_, Some(origin)) => println!("{} is still set by {}, which can't be edited", key, describe_origin(&origin)),
+            (false, None) => println!("No value found for key: {}", key),
+            (true, None) => (),
+        }
+
+        if removed {
+            sync(&mut config);
+        }
+        return;
+    }
+
+    if (args.add || args.remove) && config.provider.is_secret(&key) {
+        println!("Error: {} is a secret, set the whole value again with --secret", key);
+        return;
+    }
+
+    if args.add || args.remove {
+        let raw = args.value.clone().unwrap_or_default();
+        let item = match kind {
+            Some(kind) => match schema::parse_as(kind, &raw) {
+                Some(item) => item,
+                None => {
+                    println!("Error: Invalid {} value: {}", kind, raw);
+                    return;
                 }
+            },
+            None => Value::String(raw),
+        };
+
+        let changed = match (target, args.add) {
+            (Some(target), true) => config.provider.add_value_in(target, &key, item.clone()),
+            (None, true) => config.provider.add_value(&key, item.clone()),
+            (Some(target), false) => config.provider.remove_value_in(target, &key, &item),
+            (None, false) => config.provider.remove_value(&key, &item),
+        };
+
+        if !changed {
+            match (args.add, target) {
+                (true, Some(target)) => println!("No writable {} config found", target),
+                (true, None) => println!("No writable config found"),
+                (false, _) => {
+                    let origin = config.provider.get_origins(&key).into_iter()
+                        .filter(|o| target.is_none() || o.target == target)
+                        .find(|o| o.value.as_array().map(|items| items.contains(&item)).unwrap_or(false));
+                    match origin {
+                        Some(origin) => println!("Value is set by {}, which can't be edited", describe_origin(&origin)),
+                        None => println!("Value not found for key: {}", key),
+                    }
+                },
+            }
+            return;
+        }
+
+        sync(&mut config);
+        return;
+    }
+
+    match args.value {
+        Some(raw) => {
+            let parsed = match kind {
+                Some(kind) => schema::parse_value_as(&key, &raw, kind),
+                None => schema::parse_value(&key, &raw),
+            };
+            let val = match parsed {
+                Ok(val) => val,
+                Err(e) => {
+                    println!("Error: {}", e.message);
+                    return;
+                }
+            };
+
+            // Keep a secret encrypted when it is overwritten without --secret.
+            let result = match (target, args.secret || config.provider.is_secret(&key)) {
+                (Some(target), true) => config.provider.set_secret_in(target, &key, val),
+                (Some(target), false) => Ok(config.provider.set_value_in(target, &key, val)),
+                (None, true) => config.provider.set_secret(&key, val).map(|_| true),
+                (None, false) => {
+                    config.provider.set_value(&key, val);
+                    Ok(true)
+                },
+            };
+
+            match (result, target) {
+                (Ok(true), _) => {},
+                (Ok(false), Some(target)) => {
+                    println!("No writable {} config found", target);
+                    return;
+                },
+                (Ok(false), None) => {
+                    println!("No writable config found");
+                    return;
+                },
+                (Err(e), _) => {
+                    println!("Error: {}", e.message);
+                    return;
+                },
             }
+
+            sync(&mut config);
         },
         None => {
-            match config.provider.get_value(&key) {
-                Some(val) => println!("{}", val),
-                None => println!("No value found for key: {}", key)
+            let value = match target {
+                Some(target) => config.provider.resolve_value_in(target, &key),
+                None => config.provider.resolve_merged(&key, schema::merge_policy(&key)).map(|value| value.or_else(|| {
+                    KnownConfigs::from_str(&key).and_then(|known| known.schema().default_value())
+                })),
+            };
+
+            match value {
+                Ok(Some(val)) => println!("{}", val),
+                Ok(None) => println!("No value found for key: {}", key),
+                Err(e) => println!("Error: {}", e.message),
+            }
+        }
+    }
+}
+
+fn list_known() {
+    println!("Known config keys:");
+    for key in KnownConfigs::list() {
+        let schema = match KnownConfigs::from_str(key) {
+            Some(known) => known.schema(),
+            None => continue,
+        };
+
+        match schema.default {
+            Some(default) => println!(" - {} ({}, default: {})", key, schema.kind, default),
+            None => println!(" - {} ({})", key, schema.kind),
+        }
+        println!("     {}", schema.description);
+        if !schema.allowed.is_empty() {
+            println!("     Allowed: {}", schema.allowed.join(", "));
+        }
+    }
+}
+
+fn list_env(config: &AppConfig) {
+    for layer in config.provider.iter() {
+        let env = match layer {
+            Config::Env(env) => env,
+            _ => continue,
+        };
+
+        let keys = env.keys();
+        if keys.is_empty() {
+            continue;
+        }
+
+        println!("Environment overrides:");
+        for key in keys {
+            if let Some(value) = env.get_value(&key) {
+                let value = match secret::is_secret(&value) {
+                    true => secret::MASK.to_string(),
+                    false => value,
+                };
+                println!(" - {} = {}", key, value);
+            }
+        }
+    }
+}
+
+fn show_matching(config: &AppConfig, pattern: &str, target: Option<ConfigTarget>, with_origin: bool) {
+    let mut found = false;
+    for (key, origin) in config.provider.get_matching(pattern) {
+        let value = match target {
+            Some(target) => match config.provider.get_value_in(target, &key) {
+                Some(value) => value,
+                None => continue,
+            },
+            None => origin.value.clone(),
+        };
+
+        found = true;
+        println!("{} = {}", key, value);
+        if with_origin && target.is_none() {
+            println!("  from {}", describe_origin(&origin));
+        }
+    }
+
+    if !found {
+        println!("No value found for key: {}", pattern);
+    }
+}
+
+fn show_history(config: &AppConfig, key: Option<&str>) {
+    let history = match config.provider.history() {
+        Some(history) => history,
+        None => {
+            println!("Config history is not available");
+            return;
+        }
+    };
+
+    let entries = match history.read() {
+        Ok(entries) => entries,
+        Err(e) => {
+            println!("Error: {}", e.message);
+            return;
+        }
+    };
+
+    let entries: Vec<HistoryEntry> = entries.into_iter()
+        .filter(|e| key.map(|k| e.key == k).unwrap_or(true))
+        .collect();
+    if entries.is_empty() {
+        println!("No recorded changes");
+        return;
+    }
+
+    for entry in entries {
+        println!("{}", describe_entry(&entry));
+    }
+}
+
+fn undo(config: &AppConfig) {
+    let history = match config.provider.history() {
+        Some(history) => history,
+        None => {
+            println!("Config history is not available");
+            return;
+        }
+    };
+
+    match history.undo() {
+        Ok(entries) => {
+            for entry in entries {
+                println!("{}", describe_entry(&entry));
             }
+        },
+        Err(e) => println!("Error: {}", e.message),
+    }
+}
+
+fn describe_entry(entry: &HistoryEntry) -> String {
+    let time = humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(entry.timestamp));
+    let action = match (&entry.old, &entry.new) {
+        (_, Some(new)) => format!("set {} = {}", entry.key, secret::mask(new.clone())),
+        (_, None) => format!("unset {}", entry.key),
+    };
+    let was = match &entry.old {
+        Some(old) => format!(" (was {})", secret::mask(old.clone())),
+        None => String::new(),
+    };
+    let undo = match entry.undoes {
+        Some(_) => " [undo]",
+        None => "",
+    };
+    let layer = match (&entry.layer, &entry.path) {
+        (Some(layer), Some(path)) => format!(" in {} {}", layer, path.display()),
+        (None, Some(path)) => format!(" in {}", path.display()),
+        (Some(layer), None) => format!(" in {}", layer),
+        (None, None) => String::new(),
+    };
+
+    format!("{} {} {}{}{}{}", time, entry.user, action, was, layer, undo)
+}
+
+fn dump(config: &AppConfig, as_json: bool) {
+    let entries = config.provider.entries();
+    if as_json {
+        let items: Vec<Value> = entries.into_iter()
+            .map(|(key, origin)| json!({
+                "key": key,
+                "value": origin.value,
+                "layer": origin.target.map(|t| t.to_str()).unwrap_or("default"),
+                "path": origin.path,
+                "priority": origin.priority,
+            }))
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&items).unwrap_or_default());
+        return;
+    }
+
+    if entries.is_empty() {
+        println!("No config values found");
+        return;
+    }
+
+    for (key, origin) in entries {
+        println!("{} = {}", key, origin.value);
+        println!("  from {}", describe_origin(&origin));
+    }
+}
+
+fn export(config: &AppConfig, file: Option<PathBuf>, layer: Option<ConfigTarget>) {
+    let values = without_secrets(config.provider.layer_values(layer));
+    let file = match file {
+        Some(file) => file,
+        None => {
+            println!("{}", serde_json::to_string_pretty(&values).unwrap_or_default());
+            return;
         }
+    };
+
+    let keys = key_path::keys(&values);
+    let mut json = ConfigJson::new(Box::new(ConfigFile::new(file.clone())), true);
+    for key in &keys {
+        if let Some(value) = key_path::get(&values, key) {
+            json.set_value(key, value.clone());
+        }
+    }
+
+    match json.save() {
+        Ok(_) => println!("Exported {} keys to {}", keys.len(), file.display()),
+        Err(e) => println!("Error: Cannot write {}: {}", file.display(), e),
+    }
+}
+
+fn import(config: &mut AppConfig, file: PathBuf, replace: bool, target: Option<ConfigTarget>) {
+    let source = match Config::from_file(file.clone()) {
+        Ok(source) => source,
+        Err(e) => {
+            println!("Error: Cannot read {}: {}", file.display(), e);
+            return;
+        }
+    };
+
+    let mut values = Value::Object(Map::new());
+    for key in source.keys() {
+        if let Some(value) = source.get_value(&key) {
+            key_path::set(&mut values, &key, value);
+        }
+    }
+
+    match config.provider.import(target, &without_secrets(values), replace) {
+        Ok(true) => sync(config),
+        Ok(false) => match target {
+            Some(target) => println!("No writable {} config found", target),
+            None => println!("No writable config found"),
+        },
+        Err(e) => println!("Error: {}", e.message),
+    }
+}
+
+/// Drops the encrypted values, which can only be read with the key of
+/// the machine that wrote them.
+fn without_secrets(mut values: Value) -> Value {
+    for key in key_path::keys(&values) {
+        let is_secret = key_path::get(&values, &key).map(secret::contains_secret).unwrap_or(false);
+        if is_secret {
+            eprintln!("Skipped secret {}, set it again with --secret", key);
+            key_path::remove(&mut values, &key);
+        }
+    }
+    values
+}
+
+fn diff(config: &AppConfig, from: ConfigTarget, to: ConfigTarget) {
+    let changes = key_path::diff(
+        &config.provider.layer_values(Some(from)),
+        &config.provider.layer_values(Some(to)),
+    );
+
+    if changes.is_empty() {
+        println!("No differences between {} and {}", from, to);
+        return;
+    }
+
+    println!("--- {}", from);
+    println!("+++ {}", to);
+    print_changes(changes, "");
+}
+
+fn print_changes(changes: Vec<(String, Option<Value>, Option<Value>)>, indent: &str) {
+    for (key, old, new) in changes {
+        if let Some(old) = old {
+            println!("{}- {} = {}", indent, key, secret::mask(old));
+        }
+        if let Some(new) = new {
+            println!("{}+ {} = {}", indent, key, secret::mask(new));
+        }
+    }
+}
+
+fn migrate(config: &mut AppConfig, dry_run: bool) {
+    let mut pending = false;
+    for layer in config.provider.iter() {
+        let migrations = layer.migrations();
+        let path = match layer.path() {
+            Some(path) if !migrations.is_empty() => path,
+            _ => continue,
+        };
+
+        pending = true;
+        println!("{}", path.display());
+        for migration in migrations {
+            println!("  {}: {}", migration.version, migration.description);
+        }
+        print_changes(layer.migration_changes(), "    ");
+    }
+
+    if !pending {
+        println!("Config files are up to date");
+        return;
+    }
+
+    match dry_run {
+        true => println!("Dry run, nothing was written"),
+        false => {
+            config.provider.stage_migrations();
+            sync(config);
+        },
+    }
+}
+
+fn sync(config: &mut AppConfig) {
+    let result = config.provider.sync();
+    for r in result {
+        match r {
+            Ok(_) => continue,
+            Err(e) => println!("Error: {}", e)
+        }
+    }
+}
+
+fn show_origin(config: &AppConfig, key: &str, target: Option<ConfigTarget>) {
+    let origins: Vec<ConfigOrigin> = config.provider.get_origins(key).into_iter()
+        .filter(|o| target.is_none() || o.target == target)
+        .collect();
+
+    let (effective, shadowed) = match origins.split_first() {
+        Some(e) => e,
+        None => {
+            println!("No value found for key: {}", key);
+            return;
+        }
+    };
+
+    println!("{}", secret::mask(effective.value.clone()));
+    println!("  from {}", describe_origin(effective));
+    for origin in shadowed {
+        println!("  shadows {} from {}", secret::mask(origin.value.clone()), describe_origin(origin));
+    }
+}
+
+fn describe_origin(origin: &ConfigOrigin) -> String {
+    let layer = match origin.target {
+        Some(target) => target.to_str(),
+        None => "default",
+    };
+
+    match &origin.path {
+        Some(path) => format!("{} {} (priority {})", layer, path.display(), origin.priority),
+        None => format!("{} (priority {})", layer, origin.priority),
     }
 }
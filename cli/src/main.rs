@@ -8,14 +8,18 @@ use args::{Cli, Commands};
 use clap::Parser;
 
 use logger::{configure_logger, configure_logger_from_config};
-use script_herder_core::config::AppConfig;
+use script_herder_core::config::{AppConfig, LoadOptions};
+use script_herder_core::config::history::History;
+use script_herder_core::config::secret::Secrets;
+use script_herder_core::infra::app_dirs::AppDirs;
 
 fn main() {
     let cli = Cli::parse();
-    let config_path = match cli.config {
-        Some(path) => path,
-        None => get_config_path()
-    };
+    let mut config_files = Vec::new();
+    if cli.config.is_empty() || cli.with_machine_config {
+        config_files.push(get_config_path());
+    }
+    config_files.extend(cli.config);
 
     let verbose = cli.verbose;
 
@@ -23,16 +27,39 @@ fn main() {
         configure_logger(log::LevelFilter::Trace).unwrap();
     }
 
-    let mut config = AppConfig::from_json(config_path).unwrap();
+    let overrides = match cli.overrides.iter().map(|o| AppConfig::parse_override(o)).collect() {
+        Ok(overrides) => overrides,
+        Err(e) => {
+            println!("Error: {}", e.message);
+            return;
+        }
+    };
+
+    let options = LoadOptions { files: config_files, profile: cli.profile, dir: None };
+    let mut config = match AppConfig::load(options) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("Error: {}", e.message);
+            return;
+        }
+    };
     config.use_env();
+    if let Some(dirs) = AppDirs::from_env() {
+        config.provider.use_history(History::new(dirs.history_file()));
+        config.provider.use_secrets(Secrets::new(dirs.secret_key_file()));
+    }
+    if let Err(e) = config.use_overrides(overrides) {
+        println!("Error: {}", e.message);
+        return;
+    }
 
     if !verbose {
         configure_logger_from_config(&config).unwrap();
     }
 
     match cli.command {
-        Some(Commands::Config { key, value, list }) =>
-            commands::config::run_config(config, key.unwrap_or("".to_string()), value, list),
+        Some(Commands::Config(args)) =>
+            commands::config::run_config(config, args),
         Some(Commands::Repo) =>
             commands::repo::run_repo_info(config),
         None => print!("Not a valid command")
@@ -40,10 +67,16 @@ fn main() {
 }
 
 fn get_config_path() -> PathBuf {
-    match dirs::home_dir() {
-        Some(mut path) => {
-            path.push(".config-sh.json");
-            path
+    match AppDirs::from_env() {
+        Some(dirs) => {
+            match AppConfig::migrate_legacy_config(&dirs) {
+                // Runs before the logger is set up; stderr keeps stdout clean
+                // for the command output.
+                Ok(Some((from, to))) => eprintln!("Moved config file from {} to {}", from.display(), to.display()),
+                Ok(None) => (),
+                Err(e) => eprintln!("Error: cannot move legacy config file: {}", e.message),
+            }
+            dirs.config_file()
         },
         None => PathBuf::from(".config-sh.json")
     }
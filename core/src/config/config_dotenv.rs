@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use crate::config::config_env::from_env_name;
+use crate::infra::io::{ConfigIO, ConfigFile, ConfigData};
+
+use std::error::Error;
+
+
+/// Read-only config source for dotenv files (`KEY=value` lines).
+///
+/// Variable names are mapped to config keys like `ConfigEnv` does, so with
+/// the `SH_` prefix `SH_CORE_REPO_PATH=...` sets `core.repo.path`. Lines
+/// without the prefix are ignored.
+pub struct ConfigDotEnv {
+    io: Box<dyn ConfigIO>,
+    prefix: Option<String>,
+    data: Option<HashMap<String, String>>,
+}
+
+impl ConfigDotEnv {
+    pub fn new(io: Box<dyn ConfigIO>, prefix: Option<String>) -> ConfigDotEnv {
+        ConfigDotEnv { io, prefix, data: None }
+    }
+
+    pub fn from_file(path: PathBuf, prefix: Option<String>) -> Result<ConfigDotEnv, Box<dyn Error>> {
+        let io = Box::new(ConfigFile::new(path));
+        let mut cfg = ConfigDotEnv::new(io, prefix);
+        cfg.load()?;
+        Ok(cfg)
+    }
+
+    pub fn from_data(data: String, prefix: Option<String>) -> Result<ConfigDotEnv, Box<dyn Error>> {
+        let io = Box::new(ConfigData::new(data));
+        let mut cfg = ConfigDotEnv::new(io, prefix);
+        cfg.load()?;
+        Ok(cfg)
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.data.is_some()
+    }
+
+    pub fn path(&self) -> Option<PathBuf> {
+        self.io.path()
+    }
+
+    pub fn load(&mut self) -> Result<(), Box<dyn Error>> {
+        let content = self.io.read()?;
+        let mut data = HashMap::new();
+        for (name, value) in parse(&content)? {
+            if let Some(key) = from_env_name(self.prefix.as_deref(), &name) {
+                data.insert(key, value);
+            }
+        }
+
+        self.data = Some(data);
+        Ok(())
+    }
+
+    pub fn get_value(&self, key: &str) -> Option<String> {
+        self.data.as_ref().and_then(|data| data.get(key)).cloned()
+    }
+
+    /// Lists the config keys set in the file.
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.data.iter().flat_map(|data| data.keys().cloned()).collect();
+        keys.sort();
+        keys
+    }
+
+    pub fn get<T: std::str::FromStr>(&self, key: &str) -> Option<T> {
+        match self.get_value(key) {
+            Some(value) => {
+                value.parse::<T>().ok()
+            },
+            None => None,
+        }
+    }
+}
+
+/// Parses dotenv content into variable name and value pairs, in file order.
+///
+/// Supports `#` comments, an optional `export` keyword, single quoted
+/// (literal) values, double quoted values with `\n`, `\t`, `\"` and `\\`
+/// escapes spanning several lines, and unquoted values with trailing
+/// ` # comments`.
+pub fn parse(content: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let mut vars = Vec::new();
+    let mut lines = content.lines().enumerate();
+
+    while let Some((index, line)) = lines.next() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").map(str::trim_start).unwrap_or(line);
+        let (name, raw) = match line.split_once('=') {
+            Some((name, raw)) if !name.trim().is_empty() => (name.trim(), raw.trim_start()),
+            _ => return Err(format!("Invalid dotenv line {}: {}", index + 1, line).into()),
+        };
+
+        let value = match raw.chars().next() {
+            Some('"') => {
+                let mut text = raw[1..].to_string();
+                loop {
+                    if let Some(value) = unquote_double(&text) {
+                        break value;
+                    }
+                    match lines.next() {
+                        Some((_, next)) => {
+                            text.push('\n');
+                            text.push_str(next);
+                        },
+                        None => return Err(format!("Unterminated quote on dotenv line {}", index + 1).into()),
+                    }
+                }
+            },
+            Some('\'') => match raw[1..].find('\'') {
+                Some(end) => raw[1..end + 1].to_string(),
+                None => return Err(format!("Unterminated quote on dotenv line {}", index + 1).into()),
+            },
+            _ => match raw.find(" #") {
+                Some(start) => raw[..start].trim_end().to_string(),
+                None => raw.trim_end().to_string(),
+            },
+        };
+
+        vars.push((name.to_string(), value));
+    }
+
+    Ok(vars)
+}
+
+/// Reads a double quoted value up to its closing quote, None when the
+/// quote is not closed in `text`.
+fn unquote_double(text: &str) -> Option<String> {
+    let mut value = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(value),
+            '\\' => match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some(other) => value.push(other),
+                None => return None,
+            },
+            c => value.push(c),
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reads_plain_values_and_skips_comments() {
+        let vars = parse("# comment\n\nA=1\nexport B = two words # note\nC=\n").unwrap();
+        assert_eq!(vars, vec![
+            ("A".to_string(), "1".to_string()),
+            ("B".to_string(), "two words".to_string()),
+            ("C".to_string(), "".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn parse_reads_quoted_values() {
+        let vars = parse("A=\"say \\\"hi\\\" # not a comment\"\nB='raw \\n #'\nC=\"line\none\"").unwrap();
+        assert_eq!(vars, vec![
+            ("A".to_string(), "say \"hi\" # not a comment".to_string()),
+            ("B".to_string(), "raw \\n #".to_string()),
+            ("C".to_string(), "line\none".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn parse_fails_on_invalid_lines() {
+        assert!(parse("NOVALUE").is_err());
+        assert!(parse("A=\"open").is_err());
+        assert!(parse("A='open").is_err());
+    }
+
+    #[test]
+    fn load_maps_prefixed_names_to_keys() {
+        let src = ConfigDotEnv::from_data(
+            "SH_CORE_GIT_USER=me\nSH__core.repo.path=repo\nDATABASE_URL=db".to_string(),
+            Some("SH_".to_string()),
+        ).unwrap();
+        assert!(src.is_loaded());
+        assert_eq!(src.get_value("core.git.user"), Some("me".to_string()));
+        assert_eq!(src.get_value("core.repo.path"), Some("repo".to_string()));
+        assert_eq!(src.keys(), vec!["core.git.user".to_string(), "core.repo.path".to_string()]);
+    }
+
+    #[test]
+    fn get_parses_value() {
+        let src = ConfigDotEnv::from_data("SH_COUNT=3".to_string(), Some("SH_".to_string())).unwrap();
+        assert_eq!(src.get::<i32>("count"), Some(3));
+    }
+}
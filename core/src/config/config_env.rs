@@ -1,5 +1,11 @@
 use std::env;
 
+use crate::config::{KnownConfigs, CONTROL_ENV};
+
+/// Reads config values from environment variables.
+///
+/// A key such as `core.repo.path` is read from `SH_CORE_REPO_PATH` when the
+/// prefix is `SH_`. The legacy `SH__core.repo.path` form is still accepted.
 pub struct ConfigEnv {
     use_config: bool,
     prefix: Option<String>
@@ -17,6 +23,7 @@ impl ConfigEnv {
         self.prefix.clone()
     }
 
+    /// Legacy variable name, the prefix and the key joined by `_`.
     pub fn get_key(&self, key: &str) -> String {
         match &self.prefix {
             Some(prefix) => format!("{}_{}", prefix, key),
@@ -24,31 +31,89 @@ impl ConfigEnv {
         }
     }
 
+    /// Variable name for the key, see `to_env_name`.
+    pub fn get_env_name(&self, key: &str) -> String {
+        to_env_name(self.prefix.as_deref(), key)
+    }
+
     pub fn get_value(&self, key: &str) -> Option<String> {
-        let actual_key = self.get_key(key);
-        if self.use_config {
-            match env::var(actual_key) {
-                Ok(val) => Some(val),
-                Err(_) => None
-            }
+        let name = self.get_env_name(key);
+        if self.use_config && !CONTROL_ENV.contains(&name.as_str()) {
+            env::var(name)
+                .or_else(|_| env::var(self.get_key(key)))
+                .ok()
         } else {
             None
         }
     }
 
+    /// Lists the config keys set through variables with the prefix.
+    pub fn keys(&self) -> Vec<String> {
+        if !self.use_config {
+            return Vec::new();
+        }
+
+        let mut keys: Vec<String> = env::vars_os()
+            .filter_map(|(name, _)| name.into_string().ok())
+            .filter_map(|name| from_env_name(self.prefix.as_deref(), &name))
+            .collect();
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+
     pub fn get<T: std::str::FromStr>(&self, key: &str) -> Option<T> {
         match self.get_value(key) {
             Some(value) => {
-                match value.parse::<T>() {
-                    Ok(value) => Some(value),
-                    Err(_) => None,
-                }
+                value.parse::<T>().ok()
             },
             None => None,
         }
     }
 }
 
+/// Maps a config key to a shell friendly variable name: uppercase, with
+/// dots and dashes turned into underscores (`core.repo.path` with prefix
+/// `SH_` becomes `SH_CORE_REPO_PATH`).
+pub fn to_env_name(prefix: Option<&str>, key: &str) -> String {
+    let name = key.to_uppercase().replace(['.', '-'], "_");
+    match prefix.map(|p| p.trim_end_matches('_')) {
+        Some(prefix) if !prefix.is_empty() => format!("{}_{}", prefix, name),
+        _ => name,
+    }
+}
+
+/// Maps a variable name back to a config key, None if it does not have
+/// the prefix or is one of the `CONTROL_ENV` variables. Known keys are
+/// matched first so their exact spelling is kept; other names are
+/// lowercased with underscores read as dots.
+pub fn from_env_name(prefix: Option<&str>, name: &str) -> Option<String> {
+    if CONTROL_ENV.contains(&name) {
+        return None;
+    }
+
+    let rest = match prefix.map(|p| p.trim_end_matches('_')) {
+        Some(prefix) if !prefix.is_empty() => name.strip_prefix(prefix)?.strip_prefix('_')?,
+        _ => name,
+    };
+
+    if rest.is_empty() {
+        return None;
+    }
+
+    if let Some(legacy) = rest.strip_prefix('_') {
+        return Some(legacy.to_string());
+    }
+
+    let known = KnownConfigs::list().into_iter()
+        .find(|key| to_env_name(None, key) == rest);
+
+    match known {
+        Some(key) => Some(key.to_string()),
+        None => Some(rest.to_lowercase().replace('_', ".")),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -67,6 +132,49 @@ mod tests {
         assert_eq!(key, "key");
     }
 
+    #[test]
+    fn to_env_name_maps_dotted_key() {
+        assert_eq!(to_env_name(Some("SH_"), "core.repo.path"), "SH_CORE_REPO_PATH");
+        assert_eq!(to_env_name(Some("SH"), "my-script.key"), "SH_MY_SCRIPT_KEY");
+        assert_eq!(to_env_name(None, "core.log.level"), "CORE_LOG_LEVEL");
+    }
+
+    #[test]
+    fn from_env_name_maps_back_to_key() {
+        assert_eq!(from_env_name(Some("SH_"), "SH_CORE_REPO_PATH"), Some("core.repo.path".to_string()));
+        assert_eq!(from_env_name(Some("SH_"), "SH_SCRIPTS_FOO"), Some("scripts.foo".to_string()));
+        assert_eq!(from_env_name(Some("SH_"), "SH__core.repo.path"), Some("core.repo.path".to_string()));
+        assert_eq!(from_env_name(Some("SH_"), "SHELL"), None);
+        assert_eq!(from_env_name(Some("SH_"), "SH_"), None);
+        assert_eq!(from_env_name(Some("SH_"), "SH_PROFILE"), None);
+    }
+
+    #[test]
+    fn get_value_reads_mapped_name() {
+        let src = ConfigEnv::new(true, Some("SHT1_".to_string()));
+        env::set_var("SHT1_CORE_GIT_USER", "mapped");
+        assert_eq!(src.get_value("core.git.user"), Some("mapped".to_string()));
+        env::remove_var("SHT1_CORE_GIT_USER");
+    }
+
+    #[test]
+    fn get_value_reads_legacy_name() {
+        let src = ConfigEnv::new(true, Some("SHT2_".to_string()));
+        env::set_var("SHT2__core.git.user", "legacy");
+        assert_eq!(src.get_value("core.git.user"), Some("legacy".to_string()));
+        env::remove_var("SHT2__core.git.user");
+    }
+
+    #[test]
+    fn keys_lists_prefixed_variables() {
+        let src = ConfigEnv::new(true, Some("SHT3_".to_string()));
+        env::set_var("SHT3_CORE_LOG_LEVEL", "info");
+        env::set_var("SHT3__core.git.user", "me");
+        assert_eq!(src.keys(), vec!["core.git.user".to_string(), "core.log.level".to_string()]);
+        env::remove_var("SHT3_CORE_LOG_LEVEL");
+        env::remove_var("SHT3__core.git.user");
+    }
+
     #[test]
     fn get_value_returns_none_if_use_config_is_false() {
         let src = ConfigEnv::new(false, None);
@@ -1,20 +1,31 @@
 use std::path::PathBuf;
+use crate::config::key_path;
+use crate::config::migrate::{self, Migration, SCHEMA_VERSION_KEY};
 use crate::infra::io::{ConfigIO, ConfigFile, ConfigData};
+use crate::infra::jsonc;
 use serde_json::Value;
 
 use std::error::Error;
 
 
+/// JSON backed config source. Comments and trailing commas are accepted,
+/// and saves only rewrite the entries that changed. Files written with an
+/// older schema version are migrated in memory on load, see `migrate`; the
+/// file is only rewritten once the source is edited or the migrations are
+/// staged with `stage_migrations`.
 pub struct ConfigJson {
     io: Box<dyn ConfigIO>,
     data: Option<Value>,
+    content: Option<String>,
+    saved: Option<Value>,
+    migrations: Vec<&'static Migration>,
     can_write: bool,
     is_synced: bool,
 }
 
 impl ConfigJson {
     pub fn new(io: Box<dyn ConfigIO>, can_write: bool) -> ConfigJson {
-        ConfigJson { io, data: None, can_write, is_synced: false }
+        ConfigJson { io, data: None, content: None, saved: None, migrations: Vec::new(), can_write, is_synced: false }
     }
 
     pub fn from_file(path: PathBuf) -> Result<ConfigJson, Box<dyn Error>> {
@@ -44,16 +55,49 @@ impl ConfigJson {
         self.can_write
     }
 
+    pub fn path(&self) -> Option<PathBuf> {
+        self.io.path()
+    }
+
     pub fn load(&mut self) -> Result<(), Box<dyn Error>> {
         let content = self.io.read()?;
-        let data: Value = serde_json::from_str(&content)?;
+        let mut data: Value = serde_json::from_str(&jsonc::strip_comments(&content))?;
+        self.saved = Some(data.clone());
+        self.migrations = migrate::migrate(&mut data);
         self.data = Some(data);
+        self.content = Some(content);
+        self.is_synced = true;
         Ok(())
     }
 
+    /// Migrations applied on load that are not saved yet.
+    pub fn migrations(&self) -> &[&'static Migration] {
+        &self.migrations
+    }
+
+    /// Marks the migrations applied on load to be written by the next
+    /// save. Returns false if there are none.
+    pub fn stage_migrations(&mut self) -> bool {
+        if self.migrations.is_empty() {
+            return false;
+        }
+
+        self.is_synced = false;
+        true
+    }
+
+    /// Lists how the stored layout changes with the migrations applied on
+    /// load, see `key_path::diff_layout`.
+    pub fn migration_changes(&self) -> Vec<(String, Option<Value>, Option<Value>)> {
+        match (&self.saved, &self.data, self.migrations.is_empty()) {
+            (Some(saved), Some(data), false) => key_path::diff_layout(saved, data),
+            _ => Vec::new(),
+        }
+    }
+
     pub fn get_value(&self, key: &str) -> Option<&Value> {
         match &self.data {
-            Some(data) => match data.get(key) {
+            Some(data) => match key_path::get(data, key) {
                 Some(serde_json::Value::Null) => None,
                 Some(e) => Some(e),
                 None => None
@@ -65,18 +109,32 @@ impl ConfigJson {
     pub fn set_value(&mut self, key: &str, value: Value) {
         match &mut self.data {
             Some(data) => {
-                data[key] = value;
+                key_path::set(data, key, value);
                 self.is_synced = false;
             },
             None => {
-                let mut data = serde_json::Map::new();
-                data.insert(key.to_string(), value.clone());
-                self.data = Some(Value::Object(data));
+                let mut data = Value::Object(serde_json::Map::new());
+                key_path::set(&mut data, key, value);
+                self.data = Some(data);
                 self.is_synced = false;
             },
         }
     }
 
+    /// Removes the key, returns true if it was present.
+    pub fn unset_value(&mut self, key: &str) -> bool {
+        let removed = match &mut self.data {
+            Some(data) => key_path::remove(data, key),
+            None => false,
+        };
+
+        if removed {
+            self.is_synced = false;
+        }
+
+        removed
+    }
+
     pub fn save(&mut self) -> Result<(), Box<dyn Error>> {
         if !self.can_write {
             return Err("Cannot write to this source".into());
@@ -87,19 +145,46 @@ impl ConfigJson {
         }
 
         let data = self.data.as_ref().unwrap();
-        let content = serde_json::to_string_pretty(data)?;
-        self.io.write(&content)?;
+        let content = match self.content.as_deref().map(|c| (c, jsonc::update(c, data))) {
+            Some((_, Some(content))) => content,
+            // A full rewrite would drop comments and trailing commas.
+            Some((content, None)) if serde_json::from_str::<Value>(content).is_err() && !content.trim().is_empty() =>
+                return Err("Cannot update the file without losing its comments, edit it by hand".into()),
+            _ => serde_json::to_string_pretty(data)?,
+        };
+        self.io.write_if_unchanged(self.content.as_deref(), &content)?;
+        self.saved = Some(data.clone());
+        self.content = Some(content);
+        self.migrations.clear();
         self.is_synced = true;
         Ok(())
     }
 
+    /// Lists the keys changed since the source was loaded or last saved,
+    /// with their saved and current values. The schema version is left
+    /// out, see `migration_changes`.
+    pub fn changes(&self) -> Vec<(String, Option<Value>, Option<Value>)> {
+        let empty = Value::Object(serde_json::Map::new());
+        let mut changes = key_path::diff(
+            self.saved.as_ref().unwrap_or(&empty),
+            self.data.as_ref().unwrap_or(&empty),
+        );
+        changes.retain(|(key, _, _)| key != SCHEMA_VERSION_KEY);
+        changes
+    }
+
+    /// Lists the dotted keys of every value in the source, without the
+    /// schema version.
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys = self.data.as_ref().map(key_path::keys).unwrap_or_default();
+        keys.retain(|key| key != SCHEMA_VERSION_KEY);
+        keys
+    }
+
     pub fn get<T: serde::de::DeserializeOwned>(&self, key: &str) -> Option<T> {
         match self.get_value(key) {
             Some(value) => {
-                match serde_json::from_value(value.clone()) {
-                    Ok(value) => Some(value),
-                    Err(_) => None,
-                }
+                serde_json::from_value(value.clone()).ok()
             },
             None => None,
         }
@@ -112,6 +197,7 @@ impl ConfigJson {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
 
@@ -156,6 +242,21 @@ mod tests {
         assert_eq!(src.get_value("key").unwrap(), "new_value");
     }
 
+    #[test]
+    fn unset_value_removes_key() {
+        let mut src = ConfigJson::from_data(r#"{ "core": { "repo": { "path": "value" } } }"#.to_string()).unwrap();
+        assert!(src.unset_value("core.repo.path"));
+        assert_eq!(src.get_value("core.repo.path"), None);
+        assert!(!src.is_synced());
+    }
+
+    #[test]
+    fn unset_value_returns_false_if_missing() {
+        let mut src = ConfigJson::from_data(r#"{ "key": "value" }"#.to_string()).unwrap();
+        assert!(!src.unset_value("other"));
+        assert!(src.is_synced());
+    }
+
     #[test]
     fn save_fails_if_cannot_write() {
         let mut src = ConfigJson::from_data(r#"{ "key": "value" }"#.to_string()).unwrap();
@@ -179,6 +280,82 @@ mod tests {
         assert_eq!(src.io.read().unwrap(), "{\n  \"key\": \"value\"\n}");
     }
 
+    #[test]
+    fn save_fails_if_source_changed_since_load() {
+        let io = Box::new(ConfigData::new(r#"{ "key": "value" }"#.to_string()));
+        let mut src = ConfigJson::new(io, true);
+        src.load().unwrap();
+        src.io.write(r#"{ "key": "other" }"#).unwrap();
+
+        src.set_value("key", Value::String("mine".to_string()));
+        assert!(src.save().is_err());
+        assert_eq!(src.io.read().unwrap(), r#"{ "key": "other" }"#);
+    }
+
+    #[test]
+    fn get_value_reads_nested_key() {
+        let src = ConfigJson::from_data(r#"{ "core": { "repo": { "path": "value" } } }"#.to_string()).unwrap();
+        assert_eq!(src.get_value("core.repo.path").unwrap(), "value");
+    }
+
+    #[test]
+    fn get_value_reads_legacy_flat_key() {
+        let src = ConfigJson::from_data(r#"{ "core.repo.path": "value" }"#.to_string()).unwrap();
+        assert_eq!(src.get_value("core.repo.path").unwrap(), "value");
+    }
+
+    #[test]
+    fn save_writes_nested_key() {
+        let io = Box::new(ConfigData::new("".to_string()));
+        let mut src = ConfigJson::new(io, true);
+        src.set_value("core.log.level", Value::String("info".to_string()));
+        assert!(src.save().is_ok());
+        assert_eq!(src.io.read().unwrap(), "{\n  \"core\": {\n    \"log\": {\n      \"level\": \"info\"\n    }\n  }\n}");
+    }
+
+    #[test]
+    fn load_accepts_comments() {
+        let src = ConfigJson::from_data("{\n  // comment\n  \"key\": \"value\",\n}".to_string()).unwrap();
+        assert_eq!(src.get_value("key").unwrap(), "value");
+    }
+
+    #[test]
+    fn save_keeps_order_and_comments() {
+        let content = "{\n  // team settings\n  \"zeta\": 1,\n  \"core\": {\n    \"log\": {\n      \"level\": \"warn\" // noisy\n    }\n  }\n}";
+        let io = Box::new(ConfigData::new(content.to_string()));
+        let mut src = ConfigJson::new(io, true);
+        src.load().unwrap();
+        src.set_value("core.log.level", Value::String("info".to_string()));
+        assert!(src.save().is_ok());
+        let saved = content.replace("warn", "info").replace("  }\n}", "  },\n  \"schema_version\": 1\n}");
+        assert_eq!(src.io.read().unwrap(), saved);
+    }
+
+    #[test]
+    fn load_migrates_old_schema() {
+        let io = Box::new(ConfigData::new(r#"{ "core.git.user": "me" }"#.to_string()));
+        let mut src = ConfigJson::new(io, true);
+        src.load().unwrap();
+        assert_eq!(src.migrations().len(), migrate::MIGRATIONS.len());
+        assert_eq!(src.keys(), vec!["core.git.user"]);
+        assert!(src.changes().is_empty());
+        assert_eq!(src.migration_changes(), vec![
+            ("\"core.git.user\"".to_string(), Some(Value::from("me")), None),
+            ("core.git.user".to_string(), None, Some(Value::from("me"))),
+            (SCHEMA_VERSION_KEY.to_string(), None, Some(Value::from(1))),
+        ]);
+        assert!(src.is_synced());
+
+        assert!(src.stage_migrations());
+        assert!(!src.is_synced());
+        assert!(src.save().is_ok());
+        assert!(src.migrations().is_empty());
+        assert_eq!(
+            serde_json::from_str::<Value>(&src.io.read().unwrap()).unwrap(),
+            serde_json::json!({ "core": { "git": { "user": "me" } }, "schema_version": 1 })
+        );
+    }
+
     #[test]
     fn get_return_none_if_null() {
         let src = ConfigJson::from_data(r#"{ "key": null }"#.to_string()).unwrap();
@@ -0,0 +1,432 @@
+use std::path::PathBuf;
+use crate::config::key_path;
+use crate::config::migrate::{self, Migration, SCHEMA_VERSION_KEY};
+use crate::infra::io::{ConfigIO, ConfigFile, ConfigData};
+use serde_json::Value;
+use toml_edit::{Document, Item, Table, TableLike};
+
+use std::error::Error;
+
+
+/// TOML backed config source.
+///
+/// Values are read through a JSON view of the document so lookups behave
+/// like `ConfigJson`, while writes go to the parsed document to keep
+/// comments and layout intact on save. Migrations are applied to the
+/// document on load like in `ConfigJson`, and saved under the same rules.
+pub struct ConfigToml {
+    io: Box<dyn ConfigIO>,
+    doc: Option<Box<Document>>,
+    data: Option<Value>,
+    content: Option<String>,
+    saved: Option<Value>,
+    migrations: Vec<&'static Migration>,
+    can_write: bool,
+    is_synced: bool,
+}
+
+impl ConfigToml {
+    pub fn new(io: Box<dyn ConfigIO>, can_write: bool) -> ConfigToml {
+        ConfigToml { io, doc: None, data: None, content: None, saved: None, migrations: Vec::new(), can_write, is_synced: false }
+    }
+
+    pub fn from_file(path: PathBuf) -> Result<ConfigToml, Box<dyn Error>> {
+        let io = Box::new(ConfigFile::new(path));
+        let mut cfg = ConfigToml::new(io, true);
+        cfg.load()?;
+        Ok(cfg)
+    }
+
+    pub fn from_data(data: String) -> Result<ConfigToml, Box<dyn Error>> {
+        let io = Box::new(ConfigData::new(data));
+        let mut cfg = ConfigToml::new(io, false);
+        cfg.load()?;
+        cfg.is_synced = true;
+        Ok(cfg)
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.doc.is_some()
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.is_synced
+    }
+
+    pub fn can_write(&self) -> bool {
+        self.can_write
+    }
+
+    pub fn path(&self) -> Option<PathBuf> {
+        self.io.path()
+    }
+
+    pub fn load(&mut self) -> Result<(), Box<dyn Error>> {
+        let content = self.io.read()?;
+        let mut doc: Document = content.parse()?;
+        let saved = table_to_json(doc.as_table());
+        let mut migrated = saved.clone();
+        self.migrations = migrate::migrate(&mut migrated);
+        for (path, _, new) in key_path::diff_segments(&saved, &migrated) {
+            let parts: Vec<&str> = path.iter().map(|p| p.as_str()).collect();
+            match new.as_ref().and_then(json_to_toml) {
+                Some(value) => set_item(doc.as_table_mut(), &parts, value),
+                None => { remove_item(doc.as_table_mut(), &parts); },
+            }
+        }
+
+        self.data = Some(table_to_json(doc.as_table()));
+        self.saved = Some(saved);
+        self.doc = Some(Box::new(doc));
+        self.content = Some(content);
+        self.is_synced = true;
+        Ok(())
+    }
+
+    /// Migrations applied on load that are not saved yet.
+    pub fn migrations(&self) -> &[&'static Migration] {
+        &self.migrations
+    }
+
+    /// Marks the migrations applied on load to be written by the next
+    /// save. Returns false if there are none.
+    pub fn stage_migrations(&mut self) -> bool {
+        if self.migrations.is_empty() {
+            return false;
+        }
+
+        self.is_synced = false;
+        true
+    }
+
+    /// Lists how the stored layout changes with the migrations applied on
+    /// load, see `key_path::diff_layout`.
+    pub fn migration_changes(&self) -> Vec<(String, Option<Value>, Option<Value>)> {
+        match (&self.saved, &self.data, self.migrations.is_empty()) {
+            (Some(saved), Some(data), false) => key_path::diff_layout(saved, data),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn get_value(&self, key: &str) -> Option<&Value> {
+        match &self.data {
+            Some(data) => match key_path::get(data, key) {
+                Some(serde_json::Value::Null) => None,
+                Some(e) => Some(e),
+                None => None
+            }
+            None => None,
+        }
+    }
+
+    /// Writes a dotted key as nested tables. TOML has no null, so a null
+    /// value removes the key instead.
+    pub fn set_value(&mut self, key: &str, value: Value) {
+        let value = match json_to_toml(&value) {
+            Some(value) => value,
+            None => {
+                self.unset_value(key);
+                return;
+            }
+        };
+
+        let doc = self.doc.get_or_insert_with(|| Box::new(Document::new()));
+        let root = doc.as_table_mut();
+        if key.contains('.') {
+            root.remove(key);
+        }
+
+        let parts: Vec<&str> = key.split('.').collect();
+        set_item(root, &parts, value);
+        self.refresh();
+    }
+
+    /// Removes the key, returns true if it was present.
+    pub fn unset_value(&mut self, key: &str) -> bool {
+        let doc = match &mut self.doc {
+            Some(doc) => doc,
+            None => return false,
+        };
+
+        let root = doc.as_table_mut();
+        let flat = key.contains('.') && root.remove(key).is_some();
+        let parts: Vec<&str> = key.split('.').collect();
+        let removed = remove_item(root, &parts) || flat;
+
+        if removed {
+            self.refresh();
+        }
+
+        removed
+    }
+
+    pub fn save(&mut self) -> Result<(), Box<dyn Error>> {
+        if !self.can_write {
+            return Err("Cannot write to this source".into());
+        }
+
+        let content = match &self.doc {
+            Some(doc) => doc.to_string(),
+            None => return Ok(()),
+        };
+
+        self.io.write_if_unchanged(self.content.as_deref(), &content)?;
+        self.saved = self.data.clone();
+        self.content = Some(content);
+        self.migrations.clear();
+        self.is_synced = true;
+        Ok(())
+    }
+
+    /// Lists the keys changed since the source was loaded or last saved,
+    /// with their saved and current values. The schema version is left
+    /// out, see `migration_changes`.
+    pub fn changes(&self) -> Vec<(String, Option<Value>, Option<Value>)> {
+        let empty = Value::Object(serde_json::Map::new());
+        let mut changes = key_path::diff(
+            self.saved.as_ref().unwrap_or(&empty),
+            self.data.as_ref().unwrap_or(&empty),
+        );
+        changes.retain(|(key, _, _)| key != SCHEMA_VERSION_KEY);
+        changes
+    }
+
+    /// Lists the dotted keys of every value in the source, without the
+    /// schema version.
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys = self.data.as_ref().map(key_path::keys).unwrap_or_default();
+        keys.retain(|key| key != SCHEMA_VERSION_KEY);
+        keys
+    }
+
+    pub fn get<T: serde::de::DeserializeOwned>(&self, key: &str) -> Option<T> {
+        match self.get_value(key) {
+            Some(value) => {
+                serde_json::from_value(value.clone()).ok()
+            },
+            None => None,
+        }
+    }
+
+    pub fn set<T: serde::Serialize>(&mut self, key: &str, value: T) {
+        let value = serde_json::to_value(value).unwrap();
+        self.set_value(key, value);
+    }
+
+    fn refresh(&mut self) {
+        self.data = self.doc.as_ref().map(|doc| table_to_json(doc.as_table()));
+        self.is_synced = false;
+    }
+}
+
+fn set_item(table: &mut dyn TableLike, parts: &[&str], mut value: toml_edit::Value) {
+    let (first, rest) = match parts.split_first() {
+        Some(e) => e,
+        None => return,
+    };
+
+    if rest.is_empty() {
+        if let Some(old) = table.get(first).and_then(|i| i.as_value()) {
+            *value.decor_mut() = old.decor().clone();
+        }
+        table.insert(first, Item::Value(value));
+        return;
+    }
+
+    if !table.get(first).map(|i| i.is_table_like()).unwrap_or(false) {
+        let mut child = Table::new();
+        child.set_implicit(true);
+        table.insert(first, Item::Table(child));
+    }
+
+    if let Some(child) = table.get_mut(first).and_then(|i| i.as_table_like_mut()) {
+        set_item(child, rest, value);
+    }
+}
+
+fn remove_item(table: &mut dyn TableLike, parts: &[&str]) -> bool {
+    let (first, rest) = match parts.split_first() {
+        Some(e) => e,
+        None => return false,
+    };
+
+    if rest.is_empty() {
+        return table.remove(first).is_some();
+    }
+
+    let removed = match table.get_mut(first).and_then(|i| i.as_table_like_mut()) {
+        Some(child) => remove_item(child, rest),
+        None => false,
+    };
+
+    let is_empty = table.get(first)
+        .and_then(|i| i.as_table_like())
+        .map(|t| t.is_empty())
+        .unwrap_or(false);
+    if removed && is_empty {
+        table.remove(first);
+    }
+
+    removed
+}
+
+fn table_to_json(table: &Table) -> Value {
+    let mut map = serde_json::Map::new();
+    for (key, item) in table.iter() {
+        if let Some(value) = item_to_json(item) {
+            map.insert(key.to_string(), value);
+        }
+    }
+    Value::Object(map)
+}
+
+fn item_to_json(item: &Item) -> Option<Value> {
+    match item {
+        Item::None => None,
+        Item::Value(value) => Some(value_to_json(value)),
+        Item::Table(table) => Some(table_to_json(table)),
+        Item::ArrayOfTables(tables) => Some(Value::Array(tables.iter().map(table_to_json).collect())),
+    }
+}
+
+fn value_to_json(value: &toml_edit::Value) -> Value {
+    match value {
+        toml_edit::Value::String(s) => Value::String(s.value().clone()),
+        toml_edit::Value::Integer(i) => Value::from(*i.value()),
+        toml_edit::Value::Float(f) => Value::from(*f.value()),
+        toml_edit::Value::Boolean(b) => Value::Bool(*b.value()),
+        toml_edit::Value::Datetime(d) => Value::String(d.value().to_string()),
+        toml_edit::Value::Array(arr) => Value::Array(arr.iter().map(value_to_json).collect()),
+        toml_edit::Value::InlineTable(table) => {
+            let mut map = serde_json::Map::new();
+            for (key, value) in table.iter() {
+                map.insert(key.to_string(), value_to_json(value));
+            }
+            Value::Object(map)
+        },
+    }
+}
+
+fn json_to_toml(value: &Value) -> Option<toml_edit::Value> {
+    match value {
+        Value::Null => None,
+        Value::Bool(b) => Some((*b).into()),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => Some(i.into()),
+            None => n.as_f64().map(|f| f.into()),
+        },
+        Value::String(s) => Some(s.as_str().into()),
+        Value::Array(items) => {
+            let mut arr = toml_edit::Array::new();
+            for item in items.iter().filter_map(json_to_toml) {
+                arr.push(item);
+            }
+            Some(arr.into())
+        },
+        Value::Object(map) => {
+            let mut table = toml_edit::InlineTable::new();
+            for (key, value) in map {
+                if let Some(value) = json_to_toml(value) {
+                    table.insert(key, value);
+                }
+            }
+            Some(table.into())
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_toml_has_init_data() {
+        let src = ConfigToml::from_data("key = \"value\"".to_string()).unwrap();
+        assert!(src.is_loaded());
+        assert_eq!(src.get_value("key").unwrap(), "value");
+    }
+
+    #[test]
+    fn load_fails_if_toml_parse_fails() {
+        let io = Box::new(ConfigData::new("key = ".to_string()));
+        let mut src = ConfigToml::new(io, true);
+        assert!(src.load().is_err());
+    }
+
+    #[test]
+    fn get_value_reads_tables() {
+        let src = ConfigToml::from_data("[core.repo]\npath = \"value\"".to_string()).unwrap();
+        assert_eq!(src.get_value("core.repo.path").unwrap(), "value");
+    }
+
+    #[test]
+    fn get_value_reads_legacy_flat_key() {
+        let src = ConfigToml::from_data("\"core.repo.path\" = \"value\"".to_string()).unwrap();
+        assert_eq!(src.get_value("core.repo.path").unwrap(), "value");
+    }
+
+    #[test]
+    fn get_return_value() {
+        let src = ConfigToml::from_data("key = 10".to_string()).unwrap();
+        let v: i32 = src.get("key").unwrap();
+        assert_eq!(v, 10);
+    }
+
+    #[test]
+    fn set_value_updates_data() {
+        let mut src = ConfigToml::from_data("[core.git]\nuser = \"me\"".to_string()).unwrap();
+        src.set_value("core.repo.path", Value::String("value".to_string()));
+        assert_eq!(src.get_value("core.repo.path").unwrap(), "value");
+        assert_eq!(src.get_value("core.git.user").unwrap(), "me");
+        assert!(!src.is_synced());
+    }
+
+    #[test]
+    fn unset_value_removes_key() {
+        let mut src = ConfigToml::from_data("[core.repo]\npath = \"value\"".to_string()).unwrap();
+        assert!(src.unset_value("core.repo.path"));
+        assert_eq!(src.get_value("core.repo.path"), None);
+        assert!(!src.unset_value("core.repo.path"));
+    }
+
+    #[test]
+    fn save_fails_if_cannot_write() {
+        let mut src = ConfigToml::from_data("key = 1".to_string()).unwrap();
+        assert!(src.save().is_err());
+    }
+
+    #[test]
+    fn save_keeps_comments() {
+        let io = Box::new(ConfigData::new("# machine config\n[core.log]\nlevel = \"warn\" # noisy\n".to_string()));
+        let mut src = ConfigToml::new(io, true);
+        src.load().unwrap();
+        src.set_value("core.log.level", Value::String("info".to_string()));
+        src.set_value("core.git.user", Value::String("me".to_string()));
+        assert!(src.save().is_ok());
+        assert_eq!(
+            src.io.read().unwrap(),
+            "schema_version = 1\n# machine config\n[core.log]\nlevel = \"info\" # noisy\n\n[core.git]\nuser = \"me\"\n"
+        );
+    }
+
+    #[test]
+    fn load_migrates_old_schema() {
+        let io = Box::new(ConfigData::new("# team\n\"core.git.user\" = \"me\"\n".to_string()));
+        let mut src = ConfigToml::new(io, true);
+        src.load().unwrap();
+        assert_eq!(src.migrations().len(), migrate::MIGRATIONS.len());
+        assert_eq!(src.keys(), vec!["core.git.user"]);
+        assert!(src.changes().is_empty());
+        assert_eq!(src.migration_changes(), vec![
+            ("\"core.git.user\"".to_string(), Some(Value::from("me")), None),
+            ("core.git.user".to_string(), None, Some(Value::from("me"))),
+            (SCHEMA_VERSION_KEY.to_string(), None, Some(Value::from(1))),
+        ]);
+        assert!(src.is_synced());
+
+        assert!(src.stage_migrations());
+        assert!(src.save().is_ok());
+        assert!(src.migrations().is_empty());
+        assert_eq!(src.io.read().unwrap(), "schema_version = 1\n\n[core.git]\nuser = \"me\"\n");
+    }
+}
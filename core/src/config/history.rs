@@ -0,0 +1,258 @@
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::{Config, ConfigTarget};
+use crate::infra::error::CoreError;
+
+/// A key changed by a save, as recorded in the history journal.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Groups the changes saved by the same command.
+    pub change: String,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub user: String,
+    pub key: String,
+    pub layer: Option<String>,
+    pub path: Option<PathBuf>,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+    /// Change reverted by this one, set for entries written by undo.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undoes: Option<String>,
+}
+
+/// Append-only journal of config changes, one JSON entry per line.
+pub struct History {
+    path: PathBuf,
+    change: String,
+}
+
+impl History {
+    pub fn new(path: PathBuf) -> History {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        History {
+            path,
+            change: format!("{}-{}", now.as_nanos(), std::process::id()),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Builds an entry for a change saved by this process.
+    pub fn entry(&self, key: String, target: Option<ConfigTarget>, path: Option<PathBuf>, old: Option<Value>, new: Option<Value>) -> HistoryEntry {
+        HistoryEntry {
+            change: self.change.clone(),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default(),
+            user: current_user(),
+            key,
+            layer: target.map(|t| t.to_str().to_string()),
+            path,
+            old,
+            new,
+            undoes: None,
+        }
+    }
+
+    pub fn append(&self, entries: &[HistoryEntry]) -> Result<(), CoreError> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut lines = String::new();
+        for entry in entries {
+            let line = serde_json::to_string(entry).map_err(|e| CoreError::for_err(Box::new(e)))?;
+            lines.push_str(&line);
+            lines.push('\n');
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all(lines.as_bytes())?;
+        Ok(())
+    }
+
+    /// Reads every entry, oldest first. Lines that can't be parsed are
+    /// skipped with a warning.
+    pub fn read(&self) -> Result<Vec<HistoryEntry>, CoreError> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(content.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .filter_map(|(index, line)| match serde_json::from_str(line) {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    warn!("Skipping history line {}: {}", index + 1, e);
+                    None
+                },
+            })
+            .collect())
+    }
+
+    /// Reverts the latest change that was not undone yet, writing the
+    /// files it touched directly so it works from any folder. Fails without
+    /// writing anything if a key was changed again since.
+    pub fn undo(&self) -> Result<Vec<HistoryEntry>, CoreError> {
+        let entries = self.read()?;
+        let change = match last_change(&entries) {
+            Some(change) => change,
+            None => return Err(CoreError::for_app("No change to undo".to_string())),
+        };
+
+        let mut files: Vec<(PathBuf, Config)> = Vec::new();
+        for entry in change.iter().rev() {
+            let path = match &entry.path {
+                Some(path) => path,
+                None => return Err(CoreError::for_app(format!("Cannot undo {}, it was not saved to a file", entry.key))),
+            };
+
+            if !files.iter().any(|(p, _)| p == path) {
+                let config = Config::from_file(path.clone()).map_err(CoreError::for_err)?;
+                files.push((path.clone(), config));
+            }
+
+            let config = match files.iter_mut().find(|(p, _)| p == path) {
+                Some((_, config)) => config,
+                None => continue,
+            };
+
+            if config.get_value(&entry.key) != entry.new {
+                return Err(CoreError::for_app(format!(
+                    "Cannot undo, {} in {} was changed again since", entry.key, path.display()
+                )));
+            }
+
+            match &entry.old {
+                Some(old) => { config.set_value(&entry.key, old.clone()); },
+                None => { config.unset(&entry.key); },
+            }
+        }
+
+        for (_, config) in files.iter_mut() {
+            config.save().map_err(CoreError::for_err)?;
+        }
+
+        let reverted: Vec<HistoryEntry> = change.iter().rev()
+            .map(|entry| HistoryEntry {
+                layer: entry.layer.clone(),
+                undoes: Some(entry.change.clone()),
+                ..self.entry(entry.key.clone(), None, entry.path.clone(), entry.new.clone(), entry.old.clone())
+            })
+            .collect();
+        self.append(&reverted)?;
+        Ok(reverted)
+    }
+}
+
+/// Entries of the latest change that is not an undo and was not undone.
+pub fn last_change(entries: &[HistoryEntry]) -> Option<Vec<HistoryEntry>> {
+    let undone: HashSet<&str> = entries.iter()
+        .filter_map(|e| e.undoes.as_deref())
+        .collect();
+
+    let last = entries.iter().rev()
+        .find(|e| e.undoes.is_none() && !undone.contains(e.change.as_str()))?;
+
+    Some(entries.iter().filter(|e| e.change == last.change).cloned().collect())
+}
+
+fn current_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::infra::testing::temp_dir;
+
+    fn entry(change: &str, key: &str, undoes: Option<&str>) -> HistoryEntry {
+        let mut entry = History::new(PathBuf::new()).entry(key.to_string(), None, None, None, Some(json!(1)));
+        entry.change = change.to_string();
+        entry.undoes = undoes.map(|u| u.to_string());
+        entry
+    }
+
+    #[test]
+    fn append_and_read_round_trip() {
+        let dir = temp_dir("history-read");
+        let history = History::new(dir.join("state").join("history.jsonl"));
+        assert!(history.read().unwrap().is_empty());
+
+        let first = history.entry("a".to_string(), Some(ConfigTarget::Machine), None, None, Some(json!("x")));
+        history.append(std::slice::from_ref(&first)).unwrap();
+        history.append(&[]).unwrap();
+        assert_eq!(history.read().unwrap(), vec![first]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn last_change_skips_undone_changes() {
+        let entries = vec![
+            entry("1", "a", None),
+            entry("2", "b", None),
+            entry("2", "c", None),
+            entry("3", "d", None),
+            entry("4", "d", Some("3")),
+        ];
+        let keys: Vec<String> = last_change(&entries).unwrap().into_iter().map(|e| e.key).collect();
+        assert_eq!(keys, vec!["b", "c"]);
+        assert_eq!(last_change(&entries[4..]), None);
+    }
+
+    #[test]
+    fn undo_reverts_last_change() {
+        let dir = temp_dir("history-undo");
+        let file = dir.join("config.json");
+        fs::write(&file, r#"{ "a": "new", "b": 1 }"#).unwrap();
+
+        let history = History::new(dir.join("history.jsonl"));
+        history.append(&[
+            history.entry("a".to_string(), Some(ConfigTarget::Machine), Some(file.clone()), Some(json!("old")), Some(json!("new"))),
+            history.entry("b".to_string(), Some(ConfigTarget::Machine), Some(file.clone()), None, Some(json!(1))),
+        ]).unwrap();
+
+        let reverted = history.undo().unwrap();
+        assert_eq!(reverted.len(), 2);
+        let config = Config::from_file(file.clone()).unwrap();
+        assert_eq!(config.get_value("a"), Some(json!("old")));
+        assert_eq!(config.get_value("b"), None);
+        assert!(History::new(dir.join("history.jsonl")).undo().is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn undo_fails_if_value_changed_since() {
+        let dir = temp_dir("history-changed");
+        let file = dir.join("config.json");
+        fs::write(&file, r#"{ "a": "other" }"#).unwrap();
+
+        let history = History::new(dir.join("history.jsonl"));
+        history.append(&[
+            history.entry("a".to_string(), None, Some(file.clone()), Some(json!("old")), Some(json!("new"))),
+        ]).unwrap();
+
+        assert!(history.undo().is_err());
+        assert_eq!(fs::read_to_string(&file).unwrap(), r#"{ "a": "other" }"#);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
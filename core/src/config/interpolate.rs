@@ -0,0 +1,111 @@
+use crate::infra::error::CoreError;
+
+/// A `${...}` reference found in a config value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reference<'a> {
+    /// `${env:NAME}`, an environment variable.
+    Env(&'a str),
+    /// `${config:key}`, the resolved value of another config key.
+    Config(&'a str),
+    /// `${name}`, a variable provided by the app, such as `repo.root`.
+    Var(&'a str),
+}
+
+impl<'a> Reference<'a> {
+    pub fn parse(text: &'a str) -> Result<Reference<'a>, CoreError> {
+        let text = text.trim();
+        let reference = match text.split_once(':') {
+            Some(("env", name)) => Reference::Env(name.trim()),
+            Some(("config", key)) => Reference::Config(key.trim()),
+            Some((kind, _)) => return Err(CoreError::for_app(format!("Unknown reference type '{}' in ${{{}}}", kind, text))),
+            None => Reference::Var(text),
+        };
+
+        match reference {
+            Reference::Env(name) | Reference::Config(name) | Reference::Var(name) if name.is_empty() =>
+                Err(CoreError::for_app(format!("Empty reference ${{{}}}", text))),
+            reference => Ok(reference),
+        }
+    }
+}
+
+/// Whether the text has references to expand.
+pub fn has_references(text: &str) -> bool {
+    text.contains("${")
+}
+
+/// Replaces every `${...}` in the text with the value returned by
+/// `lookup`. `$${` is kept as a literal `${`.
+pub fn expand<F>(text: &str, mut lookup: F) -> Result<String, CoreError>
+where F: FnMut(Reference) -> Result<String, CoreError> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        let tail = &rest[start..];
+
+        if let Some(after) = tail.strip_prefix("$${") {
+            result.push_str("${");
+            rest = after;
+        } else if let Some(after) = tail.strip_prefix("${") {
+            let end = match after.find('}') {
+                Some(end) => end,
+                None => return Err(CoreError::for_app(format!("Unterminated reference in: {}", text))),
+            };
+            result.push_str(&lookup(Reference::parse(&after[..end])?)?);
+            rest = &after[end + 1..];
+        } else {
+            result.push('$');
+            rest = &tail[1..];
+        }
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(reference: Reference) -> Result<String, CoreError> {
+        match reference {
+            Reference::Env(name) => Ok(format!("env-{}", name)),
+            Reference::Config(key) => Ok(format!("config-{}", key)),
+            Reference::Var(name) => Ok(format!("var-{}", name)),
+        }
+    }
+
+    #[test]
+    fn parse_reads_reference_types() {
+        assert_eq!(Reference::parse("env:HOME").unwrap(), Reference::Env("HOME"));
+        assert_eq!(Reference::parse(" config:core.repo.path ").unwrap(), Reference::Config("core.repo.path"));
+        assert_eq!(Reference::parse("repo.root").unwrap(), Reference::Var("repo.root"));
+        assert!(Reference::parse("file:/tmp").is_err());
+        assert!(Reference::parse("env:").is_err());
+    }
+
+    #[test]
+    fn expand_replaces_references() {
+        let text = expand("${env:HOME}/scratch/${repo.root}", lookup).unwrap();
+        assert_eq!(text, "env-HOME/scratch/var-repo.root");
+    }
+
+    #[test]
+    fn expand_keeps_plain_and_escaped_text() {
+        assert_eq!(expand("cost $5, $${env:HOME}", lookup).unwrap(), "cost $5, ${env:HOME}");
+        assert_eq!(expand("no refs", lookup).unwrap(), "no refs");
+    }
+
+    #[test]
+    fn expand_fails_on_unterminated_reference() {
+        assert!(expand("${env:HOME", lookup).is_err());
+    }
+
+    #[test]
+    fn expand_returns_lookup_errors() {
+        let result = expand("${config:a}", |_| Err(CoreError::for_app("missing".to_string())));
+        assert_eq!(result.unwrap_err().message, "missing");
+    }
+}
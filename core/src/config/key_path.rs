@@ -0,0 +1,319 @@
+use std::collections::BTreeMap;
+
+use serde_json::{Map, Value};
+
+/// Resolves a dotted key (`core.repo.path`) through nested objects.
+///
+/// Falls back to the legacy flat form, where the whole dotted key is
+/// stored as a single top-level entry.
+pub fn get<'a>(data: &'a Value, key: &str) -> Option<&'a Value> {
+    match get_nested(data, key) {
+        Some(Value::Null) | None => data.get(key),
+        Some(value) => Some(value),
+    }
+}
+
+/// Writes a dotted key as nested objects, creating missing intermediate
+/// objects and replacing any non-object found along the path.
+///
+/// A legacy flat entry for the same key is removed so it can't shadow
+/// or go stale next to the nested value.
+pub fn set(data: &mut Value, key: &str, value: Value) {
+    let root = as_object_mut(data);
+    if key.contains('.') {
+        root.remove(key);
+    }
+
+    let (parents, last) = match key.rsplit_once('.') {
+        Some((parents, last)) => (Some(parents), last),
+        None => (None, key),
+    };
+
+    let mut node = root;
+    for part in parents.into_iter().flat_map(|p| p.split('.')) {
+        let child = node.entry(part.to_string())
+            .or_insert_with(|| Value::Object(Map::new()));
+        node = as_object_mut(child);
+    }
+
+    node.insert(last.to_string(), value);
+}
+
+/// Removes a dotted key from both the nested and the legacy flat form.
+///
+/// Parent objects left empty by the removal are pruned as well.
+/// Returns true if anything was removed.
+pub fn remove(data: &mut Value, key: &str) -> bool {
+    let flat = match data.as_object_mut() {
+        Some(root) if key.contains('.') => root.remove(key).is_some(),
+        Some(_) => false,
+        None => return false,
+    };
+
+    let parts: Vec<&str> = key.split('.').collect();
+    remove_nested(data, &parts) || flat
+}
+
+/// Lists the dotted keys of every leaf value, sorted. Arrays are leaves,
+/// legacy flat keys are listed as they are and nulls are skipped.
+pub fn keys(data: &Value) -> Vec<String> {
+    let mut keys = Vec::new();
+    collect_keys(data, "", &mut keys);
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+/// Lists the keys whose value differs between `old` and `new`, with the
+/// value on each side (None when the key is missing), sorted by key.
+pub fn diff(old: &Value, new: &Value) -> Vec<(String, Option<Value>, Option<Value>)> {
+    let mut all = keys(old);
+    all.extend(keys(new));
+    all.sort();
+    all.dedup();
+
+    all.into_iter()
+        .filter_map(|key| {
+            let before = get(old, &key).filter(|v| !v.is_null()).cloned();
+            let after = get(new, &key).filter(|v| !v.is_null()).cloned();
+            match before == after {
+                true => None,
+                false => Some((key, before, after)),
+            }
+        })
+        .collect()
+}
+
+/// Like `diff`, but compares the stored layout instead of the values read:
+/// a legacy flat key and its nested form are different entries. Flat keys
+/// are listed in quotes, `"core.repo.path"`, nested ones as usual.
+pub fn diff_layout(old: &Value, new: &Value) -> Vec<(String, Option<Value>, Option<Value>)> {
+    let mut changes: Vec<(String, Option<Value>, Option<Value>)> = diff_segments(old, new).into_iter()
+        .map(|(path, old, new)| (layout_key(&path), old, new))
+        .collect();
+    changes.sort_by(|a, b| a.0.cmp(&b.0));
+    changes
+}
+
+/// Changed leaves of the stored layout keyed by their path, one entry
+/// per object level, see `diff_layout`.
+pub fn diff_segments(old: &Value, new: &Value) -> Vec<(Vec<String>, Option<Value>, Option<Value>)> {
+    let mut before = BTreeMap::new();
+    collect_layout(old, &mut Vec::new(), &mut before);
+    let mut after = BTreeMap::new();
+    collect_layout(new, &mut Vec::new(), &mut after);
+
+    let mut all: Vec<Vec<String>> = before.keys().chain(after.keys()).cloned().collect();
+    all.sort();
+    all.dedup();
+
+    all.into_iter()
+        .filter_map(|path| {
+            let old = before.get(&path).cloned();
+            let new = after.get(&path).cloned();
+            match old == new {
+                true => None,
+                false => Some((path, old, new)),
+            }
+        })
+        .collect()
+}
+
+fn layout_key(path: &[String]) -> String {
+    path.iter()
+        .map(|part| match part.contains('.') {
+            true => Value::String(part.to_string()).to_string(),
+            false => part.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+fn collect_layout(node: &Value, path: &mut Vec<String>, leaves: &mut BTreeMap<Vec<String>, Value>) {
+    match node {
+        Value::Object(map) => {
+            for (key, child) in map {
+                path.push(key.to_string());
+                collect_layout(child, path, leaves);
+                path.pop();
+            }
+        },
+        Value::Null => (),
+        _ if !path.is_empty() => { leaves.insert(path.clone(), node.clone()); },
+        _ => (),
+    }
+}
+
+fn collect_keys(node: &Value, prefix: &str, keys: &mut Vec<String>) {
+    match node {
+        Value::Object(map) => {
+            for (key, child) in map {
+                let key = match prefix.is_empty() {
+                    true => key.to_string(),
+                    false => format!("{}.{}", prefix, key),
+                };
+                collect_keys(child, &key, keys);
+            }
+        },
+        Value::Null => (),
+        _ if !prefix.is_empty() => keys.push(prefix.to_string()),
+        _ => (),
+    }
+}
+
+fn remove_nested(node: &mut Value, parts: &[&str]) -> bool {
+    let map = match node.as_object_mut() {
+        Some(map) => map,
+        None => return false,
+    };
+
+    let (first, rest) = match parts.split_first() {
+        Some(e) => e,
+        None => return false,
+    };
+
+    if rest.is_empty() {
+        return map.remove(*first).is_some();
+    }
+
+    let removed = match map.get_mut(*first) {
+        Some(child) => remove_nested(child, rest),
+        None => false,
+    };
+
+    let is_empty = matches!(map.get(*first), Some(Value::Object(child)) if child.is_empty());
+    if removed && is_empty {
+        map.remove(*first);
+    }
+
+    removed
+}
+
+fn get_nested<'a>(data: &'a Value, key: &str) -> Option<&'a Value> {
+    key.split('.').try_fold(data, |node, part| node.get(part))
+}
+
+fn as_object_mut(node: &mut Value) -> &mut Map<String, Value> {
+    if !node.is_object() {
+        *node = Value::Object(Map::new());
+    }
+
+    match node {
+        Value::Object(map) => map,
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn get_resolves_nested_key() {
+        let data = json!({ "core": { "repo": { "path": "value" } } });
+        assert_eq!(get(&data, "core.repo.path").unwrap(), "value");
+    }
+
+    #[test]
+    fn diff_layout_lists_moved_flat_keys() {
+        let old = json!({ "core.repo.path": "value", "other": 1 });
+        let new = json!({ "core": { "repo": { "path": "value" } }, "other": 1 });
+        assert!(diff(&old, &new).is_empty());
+        assert_eq!(diff_layout(&old, &new), vec![
+            ("\"core.repo.path\"".to_string(), Some(json!("value")), None),
+            ("core.repo.path".to_string(), None, Some(json!("value"))),
+        ]);
+    }
+
+    #[test]
+    fn get_falls_back_to_flat_key() {
+        let data = json!({ "core.repo.path": "value" });
+        assert_eq!(get(&data, "core.repo.path").unwrap(), "value");
+    }
+
+    #[test]
+    fn get_prefers_nested_key() {
+        let data = json!({ "core.repo.path": "flat", "core": { "repo": { "path": "nested" } } });
+        assert_eq!(get(&data, "core.repo.path").unwrap(), "nested");
+    }
+
+    #[test]
+    fn get_returns_none_if_path_is_not_object() {
+        let data = json!({ "core": "value" });
+        assert_eq!(get(&data, "core.repo.path"), None);
+    }
+
+    #[test]
+    fn set_creates_intermediate_objects() {
+        let mut data = json!({});
+        set(&mut data, "core.repo.path", json!("value"));
+        assert_eq!(data, json!({ "core": { "repo": { "path": "value" } } }));
+    }
+
+    #[test]
+    fn set_keeps_sibling_keys() {
+        let mut data = json!({ "core": { "git": { "user": "me" } } });
+        set(&mut data, "core.repo.path", json!("value"));
+        assert_eq!(data, json!({ "core": { "git": { "user": "me" }, "repo": { "path": "value" } } }));
+    }
+
+    #[test]
+    fn set_replaces_non_object_parent() {
+        let mut data = json!({ "core": "value" });
+        set(&mut data, "core.repo", json!("value"));
+        assert_eq!(data, json!({ "core": { "repo": "value" } }));
+    }
+
+    #[test]
+    fn remove_deletes_nested_key_and_prunes_parents() {
+        let mut data = json!({ "core": { "repo": { "path": "value" } }, "other": 1 });
+        assert!(remove(&mut data, "core.repo.path"));
+        assert_eq!(data, json!({ "other": 1 }));
+    }
+
+    #[test]
+    fn remove_keeps_non_empty_parents() {
+        let mut data = json!({ "core": { "repo": { "path": "value" }, "git": { "user": "me" } } });
+        assert!(remove(&mut data, "core.repo.path"));
+        assert_eq!(data, json!({ "core": { "git": { "user": "me" } } }));
+    }
+
+    #[test]
+    fn remove_deletes_flat_key() {
+        let mut data = json!({ "core.repo.path": "value" });
+        assert!(remove(&mut data, "core.repo.path"));
+        assert_eq!(data, json!({}));
+    }
+
+    #[test]
+    fn remove_returns_false_if_missing() {
+        let mut data = json!({ "core": { "git": {} } });
+        assert!(!remove(&mut data, "core.repo.path"));
+        assert_eq!(data, json!({ "core": { "git": {} } }));
+    }
+
+    #[test]
+    fn set_removes_legacy_flat_key() {
+        let mut data = json!({ "core.repo.path": "flat" });
+        set(&mut data, "core.repo.path", json!("nested"));
+        assert_eq!(data, json!({ "core": { "repo": { "path": "nested" } } }));
+    }
+
+    #[test]
+    fn keys_lists_leaf_keys() {
+        let data = json!({ "core": { "repo": { "path": "p" }, "git": { "user": "me", "none": null } }, "core.log.level": "info", "list": [1] });
+        assert_eq!(keys(&data), vec!["core.git.user", "core.log.level", "core.repo.path", "list"]);
+    }
+
+    #[test]
+    fn diff_lists_changed_keys() {
+        let old = json!({ "a": 1, "core": { "git": { "user": "me" } }, "same": true });
+        let new = json!({ "b": 2, "core": { "git": { "user": "you" } }, "same": true });
+        assert_eq!(diff(&old, &new), vec![
+            ("a".to_string(), Some(json!(1)), None),
+            ("b".to_string(), None, Some(json!(2))),
+            ("core.git.user".to_string(), Some(json!("me")), Some(json!("you"))),
+        ]);
+    }
+}
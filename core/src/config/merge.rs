@@ -0,0 +1,76 @@
+use serde_json::Value;
+
+use crate::enum_str;
+
+enum_str!(MergePolicy {
+    Replace = "replace",
+    Concat = "concat",
+});
+
+/// Merges a value from a higher priority layer over one from a lower layer.
+///
+/// Objects are merged key by key, recursively, and a `null` in the upper
+/// object drops the key inherited from below. Arrays are replaced, or with
+/// `Concat` joined with the upper items first. Any other pair keeps the
+/// upper value.
+pub fn merge(lower: Value, upper: Value, policy: MergePolicy) -> Value {
+    match (lower, upper) {
+        (Value::Object(mut lower), Value::Object(upper)) => {
+            for (key, value) in upper {
+                if value.is_null() {
+                    lower.remove(&key);
+                    continue;
+                }
+
+                let merged = match lower.remove(&key) {
+                    Some(old) => merge(old, value, policy),
+                    None => value,
+                };
+                lower.insert(key, merged);
+            }
+            Value::Object(lower)
+        },
+        (Value::Array(lower), Value::Array(mut upper)) if policy == MergePolicy::Concat => {
+            upper.extend(lower);
+            Value::Array(upper)
+        },
+        (_, upper) => upper,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn merge_combines_nested_objects() {
+        let lower = json!({ "shell": "bash", "env": { "A": "1", "B": "2" } });
+        let upper = json!({ "env": { "B": "3" }, "timeout": 10 });
+        assert_eq!(
+            merge(lower, upper, MergePolicy::Replace),
+            json!({ "shell": "bash", "env": { "A": "1", "B": "3" }, "timeout": 10 })
+        );
+    }
+
+    #[test]
+    fn merge_drops_keys_set_to_null() {
+        let lower = json!({ "shell": "bash", "env": { "A": "1" } });
+        let upper = json!({ "env": null });
+        assert_eq!(merge(lower, upper, MergePolicy::Replace), json!({ "shell": "bash" }));
+    }
+
+    #[test]
+    fn merge_applies_array_policy() {
+        let lower = json!({ "paths": ["repo"] });
+        let upper = json!({ "paths": ["home"] });
+        assert_eq!(merge(lower.clone(), upper.clone(), MergePolicy::Replace), json!({ "paths": ["home"] }));
+        assert_eq!(merge(lower, upper, MergePolicy::Concat), json!({ "paths": ["home", "repo"] }));
+    }
+
+    #[test]
+    fn merge_keeps_upper_on_type_mismatch() {
+        assert_eq!(merge(json!({ "a": 1 }), json!("text"), MergePolicy::Concat), json!("text"));
+        assert_eq!(merge(json!([1]), json!({ "a": 1 }), MergePolicy::Concat), json!({ "a": 1 }));
+    }
+}
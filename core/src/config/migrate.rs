@@ -0,0 +1,125 @@
+use log::warn;
+use serde_json::Value;
+
+use crate::config::key_path;
+
+/// Key holding the version of the layout a config file was written with.
+pub const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+/// Rewrites a config file from the previous schema version to `version`.
+pub struct Migration {
+    pub version: u64,
+    pub description: &'static str,
+    pub apply: fn(&mut Value),
+}
+
+/// Every migration, in version order. Add new ones at the end when keys
+/// are renamed or moved; files written before are upgraded on load.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Store legacy flat dotted keys as nested objects",
+        apply: nest_flat_keys,
+    },
+];
+
+/// Schema version written to new and migrated files.
+pub fn current_version() -> u64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+/// Schema version of the data, 0 for files written before versioning.
+pub fn version(data: &Value) -> u64 {
+    data.get(SCHEMA_VERSION_KEY).and_then(Value::as_u64).unwrap_or(0)
+}
+
+/// Applies the migrations newer than the version of the data and sets it
+/// to the current version. Returns the migrations applied, none when the
+/// data is up to date, not an object, or from a newer version.
+pub fn migrate(data: &mut Value) -> Vec<&'static Migration> {
+    if !data.is_object() {
+        return Vec::new();
+    }
+
+    let from = version(data);
+    if from > current_version() {
+        warn!("Config schema version {} is newer than {}, leaving it as is", from, current_version());
+        return Vec::new();
+    }
+
+    let pending: Vec<&'static Migration> = MIGRATIONS.iter()
+        .filter(|m| m.version > from)
+        .collect();
+
+    for migration in &pending {
+        (migration.apply)(data);
+    }
+
+    if !pending.is_empty() {
+        key_path::set(data, SCHEMA_VERSION_KEY, Value::from(current_version()));
+    }
+    pending
+}
+
+/// Moves top level `core.repo.path` style entries into nested objects. A
+/// nested value already set for the key wins over the flat one, as it
+/// does on read.
+fn nest_flat_keys(data: &mut Value) {
+    let flat: Vec<String> = match data.as_object() {
+        Some(map) => map.keys().filter(|k| k.contains('.')).cloned().collect(),
+        None => return,
+    };
+
+    for key in flat {
+        let value = match data.as_object_mut().and_then(|map| map.remove(&key)) {
+            Some(value) => value,
+            None => continue,
+        };
+
+        if key_path::get(data, &key).map(Value::is_null).unwrap_or(true) {
+            key_path::set(data, &key, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn migrate_upgrades_unversioned_data() {
+        let mut data = json!({ "core.repo.path": "/repo", "core": { "git": { "user": "me" } } });
+        let applied = migrate(&mut data);
+        assert_eq!(applied.len(), MIGRATIONS.len());
+        assert_eq!(data, json!({
+            "core": { "git": { "user": "me" }, "repo": { "path": "/repo" } },
+            "schema_version": current_version(),
+        }));
+    }
+
+    #[test]
+    fn migrate_skips_current_and_newer_data() {
+        let mut data = json!({ "schema_version": current_version(), "a.b": 1 });
+        assert!(migrate(&mut data).is_empty());
+        assert_eq!(data["a.b"], json!(1));
+
+        let mut data = json!({ "schema_version": current_version() + 1, "a.b": 1 });
+        assert!(migrate(&mut data).is_empty());
+        assert!(migrate(&mut json!([1])).is_empty());
+    }
+
+    #[test]
+    fn nest_flat_keys_keeps_nested_value() {
+        let mut data = json!({ "core.git.user": "flat", "core": { "git": { "user": "nested" } } });
+        nest_flat_keys(&mut data);
+        assert_eq!(data, json!({ "core": { "git": { "user": "nested" } } }));
+    }
+
+    #[test]
+    fn migrations_are_in_version_order() {
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, index as u64 + 1);
+        }
+    }
+}
@@ -1,48 +1,88 @@
+pub mod config_dotenv;
 pub mod config_env;
 pub mod config_json;
+pub mod config_toml;
+pub mod history;
+pub mod interpolate;
+pub mod key_path;
+pub mod merge;
+pub mod migrate;
+pub mod overlay;
 pub mod provider;
+pub mod schema;
+pub mod secret;
 
 use serde_json::Value;
 use std::error::Error;
 use std::fs::File;
-use std::path::PathBuf;
-use log::{debug, trace};
+use std::path::{Path, PathBuf};
+use log::{debug, error, trace};
 
 use crate::config::config_json::ConfigJson;
+use crate::config::config_toml::ConfigToml;
 use crate::config::config_env::ConfigEnv;
+use crate::config::config_dotenv::ConfigDotEnv;
 use crate::enum_str;
+use crate::infra::app_dirs::AppDirs;
 use crate::infra::error::CoreError;
+use crate::infra::host::HostInfo;
 use crate::git::repo::Repo;
 
 pub enum Config {
     Json(ConfigJson),
+    Toml(ConfigToml),
     Env(ConfigEnv),
+    DotEnv(ConfigDotEnv),
     None,
 }
 
-pub enum ConfigTarget {
-    Machine,
-    Repo,
-    Local,
-    Env,
-}
+/// Prefix of the variables read from the environment and dotenv files.
+pub const ENV_PREFIX: &str = "SH_";
+
+/// Variable the command line reads the profile from when `--profile` is
+/// not given.
+pub const PROFILE_ENV: &str = "SH_PROFILE";
+
+/// Variables with the env prefix that control the tool rather than hold
+/// config values, see `config_env::from_env_name`.
+pub const CONTROL_ENV: &[&str] = &[PROFILE_ENV];
+
+/// Key of the profiles object in the machine config, and name of the
+/// folder next to it holding profile files.
+const PROFILES_KEY: &str = "profiles";
+
+/// Dotenv files read in config folders, after `.config-sh.json`.
+const DOTENV_FILES: [&str; 2] = [".config-sh.env", ".env"];
+
+enum_str!(ConfigTarget {
+    Machine = "machine",
+    Repo = "repo",
+    Local = "local",
+    Env = "env",
+    Profile = "profile",
+    Cli = "cli",
+    Overlay = "overlay",
+});
 
 impl Config {
     pub fn get<T>(&self, key: &str) -> Option<T> 
         where T: std::str::FromStr + serde::de::DeserializeOwned {
         match self {
             Config::Json(src) => src.get(key),
+            Config::Toml(src) => src.get(key),
             Config::Env(src) => src.get(key),
+            Config::DotEnv(src) => src.get(key),
             Config::None => None,
         }
     }
 
     pub fn get_value(&self, key: &str) -> Option<Value> {
         match self {
-            Config::Json(src) => src.get_value(key)
-                .map(|v| v.clone()),
+            Config::Json(src) => src.get_value(key).cloned(),
+            Config::Toml(src) => src.get_value(key).cloned(),
             Config::Env(src) => src.get_value(key)
                 .map(|v| Value::String(v.to_string())),
+            Config::DotEnv(src) => src.get_value(key).map(Value::String),
             Config::None => None,
         }
     }
@@ -54,6 +94,10 @@ impl Config {
                 json.set(key, value);
                 true
             },
+            Config::Toml(toml) => {
+                toml.set(key, value);
+                true
+            },
             _ => false,
         }
     }
@@ -64,6 +108,49 @@ impl Config {
                 json.set_value(key, value);
                 true
             },
+            Config::Toml(toml) => {
+                toml.set_value(key, value);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Appends an item to the array at the key. A missing key becomes a
+    /// one item array and a single value becomes the first item.
+    pub fn add_item(&mut self, key: &str, item: Value) -> bool {
+        if !self.is_editable() {
+            return false;
+        }
+
+        let items = match self.get_value(key) {
+            Some(Value::Array(mut items)) => {
+                items.push(item);
+                items
+            },
+            Some(value) => vec![value, item],
+            None => vec![item],
+        };
+        self.set_value(key, Value::Array(items))
+    }
+
+    /// Removes every copy of the item from the array at the key, or the key
+    /// itself when it holds just that value. Returns true if anything changed.
+    pub fn remove_item(&mut self, key: &str, item: &Value) -> bool {
+        match self.get_value(key) {
+            Some(Value::Array(items)) if items.contains(item) => {
+                let items = items.into_iter().filter(|i| i != item).collect();
+                self.set_value(key, Value::Array(items))
+            },
+            Some(value) if &value == item => self.unset(key),
+            _ => false,
+        }
+    }
+
+    pub fn unset(&mut self, key: &str) -> bool {
+        match self {
+            Config::Json(json) => json.unset_value(key),
+            Config::Toml(toml) => toml.unset_value(key),
             _ => false,
         }
     }
@@ -71,6 +158,7 @@ impl Config {
     pub fn save(&mut self) -> Result<(), Box<dyn Error>> {
         match self {
             Config::Json(json) => json.save(),
+            Config::Toml(toml) => toml.save(),
             _ => Ok(()),
         }
     }
@@ -78,9 +166,100 @@ impl Config {
     pub fn load(&mut self) -> Result<(), Box<dyn Error>> {
         match self {
             Config::Json(json) => json.load(),
+            Config::Toml(toml) => toml.load(),
+            Config::DotEnv(dotenv) => dotenv.load(),
             _ => Ok(()),
         }
     }
+
+    pub fn path(&self) -> Option<PathBuf> {
+        match self {
+            Config::Json(json) => json.path(),
+            Config::Toml(toml) => toml.path(),
+            Config::DotEnv(dotenv) => dotenv.path(),
+            _ => None,
+        }
+    }
+
+    /// Lists the keys changed since the layer was loaded or last saved,
+    /// with their saved and current values.
+    pub fn changes(&self) -> Vec<(String, Option<Value>, Option<Value>)> {
+        match self {
+            Config::Json(json) => json.changes(),
+            Config::Toml(toml) => toml.changes(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Schema migrations applied to the layer on load and not saved yet.
+    pub fn migrations(&self) -> Vec<&'static migrate::Migration> {
+        match self {
+            Config::Json(json) => json.migrations().to_vec(),
+            Config::Toml(toml) => toml.migrations().to_vec(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Marks the layer's pending migrations to be saved, see
+    /// `ConfigJson::stage_migrations`.
+    pub fn stage_migrations(&mut self) -> bool {
+        match self {
+            Config::Json(json) => json.stage_migrations(),
+            Config::Toml(toml) => toml.stage_migrations(),
+            _ => false,
+        }
+    }
+
+    /// Changes to the stored layout made by the pending migrations.
+    pub fn migration_changes(&self) -> Vec<(String, Option<Value>, Option<Value>)> {
+        match self {
+            Config::Json(json) => json.migration_changes(),
+            Config::Toml(toml) => toml.migration_changes(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Lists the keys the layer defines.
+    pub fn keys(&self) -> Vec<String> {
+        match self {
+            Config::Json(json) => json.keys(),
+            Config::Toml(toml) => toml.keys(),
+            Config::Env(env) => env.keys(),
+            Config::DotEnv(dotenv) => dotenv.keys(),
+            Config::None => Vec::new(),
+        }
+    }
+
+    /// Whether the layer accepts set/unset, as opposed to env or empty layers.
+    pub fn is_editable(&self) -> bool {
+        matches!(self, Config::Json(_) | Config::Toml(_))
+    }
+
+    /// Whether the layer is backed by something it can save to.
+    pub fn can_write(&self) -> bool {
+        match self {
+            Config::Json(json) => json.can_write(),
+            Config::Toml(toml) => toml.can_write(),
+            _ => false,
+        }
+    }
+
+    /// Whether the layer has pending changes that can be written back.
+    pub fn needs_save(&self) -> bool {
+        match self {
+            Config::Json(json) => !json.is_synced() && json.can_write(),
+            Config::Toml(toml) => !toml.is_synced() && toml.can_write(),
+            _ => false,
+        }
+    }
+
+    /// Opens a config file, picking the format from its extension.
+    pub fn from_file(path: PathBuf) -> Result<Config, Box<dyn Error>> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Ok(Config::Toml(ConfigToml::from_file(path)?)),
+            _ => Ok(Config::Json(ConfigJson::from_file(path)?)),
+        }
+    }
 }
 
 impl AsMut<Config> for Config {
@@ -89,9 +268,24 @@ impl AsMut<Config> for Config {
     }
 }
 
+/// What `AppConfig::load` reads.
+#[derive(Debug, Clone, Default)]
+pub struct LoadOptions {
+    /// Machine config files, later files taking precedence.
+    pub files: Vec<PathBuf>,
+    /// Profile to apply. When not set, the `core.profile` key of the
+    /// machine config is used. The environment is left to the caller, see
+    /// `PROFILE_ENV`.
+    pub profile: Option<String>,
+    /// Folder local config files are looked up from, the current dir when
+    /// not set.
+    pub dir: Option<PathBuf>,
+}
+
 pub struct AppConfig {
     pub provider: provider::ConfigProvider,
     root: PathBuf,
+    host: HostInfo,
 }
 
 impl AppConfig {
@@ -99,50 +293,229 @@ impl AppConfig {
         AppConfig {
             provider: provider::ConfigProvider::new(),
             root,
+            host: HostInfo::current(),
         }
     }
 
     pub fn use_env(&mut self) {
         debug!("Using environment variables");
-        self.provider.register_top(Config::Env(ConfigEnv::new(true, Some("SH_".to_string()))));
+        self.provider.register_top_for(ConfigTarget::Env, Config::Env(ConfigEnv::new(true, Some(ENV_PREFIX.to_string()))));
     }
 
+    /// Registers values that only live for this run, above every other
+    /// layer. The layer is read-only so `sync` never writes it.
+    pub fn use_overrides(&mut self, overrides: Vec<(String, Value)>) -> Result<(), CoreError> {
+        if overrides.is_empty() {
+            return Ok(());
+        }
+
+        debug!("Using {} command line overrides", overrides.len());
+        let mut layer = ConfigJson::from_data("{}".to_string()).map_err(CoreError::for_err)?;
+        for (key, value) in overrides {
+            layer.set_value(&key, value);
+        }
+
+        self.provider.register_top_for(ConfigTarget::Cli, Config::Json(layer));
+        Ok(())
+    }
+
+    /// Splits a `key=value` override and parses the value for the key.
+    pub fn parse_override(raw: &str) -> Result<(String, Value), CoreError> {
+        match raw.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => {
+                let key = key.trim().to_string();
+                let value = schema::parse_value(&key, value)?;
+                Ok((key, value))
+            },
+            _ => Err(CoreError::for_app(format!("Invalid override, expected key=value: {}", raw))),
+        }
+    }
+
+    /// Loads the machine config from the given file. If it does not exist
+    /// but a `.toml` file with the same name does, that one is used instead.
     pub fn from_json(json_path: PathBuf) -> Result<AppConfig, CoreError> {
-        debug!("Loading config file: {:?}", json_path.canonicalize());
-        let mut config = AppConfig::new(json_path.canonicalize()?);
+        AppConfig::from_files(vec![json_path])
+    }
 
-        trace!("Creating config for machine: {:?}", json_path.canonicalize());
-        let machine_config = AppConfig::create_machine_config(json_path)?;
-        trace!("Creating config for current dir: {:?}", std::env::current_dir());
-        let cwd_config = AppConfig::create_folder_config(std::env::current_dir()?, false)?;
-        let repo_config = AppConfig::create_repo_config(&machine_config, &config.root)?;
+    /// Loads each file as its own machine layer, later files taking
+    /// precedence over earlier ones. Files listed twice are loaded once.
+    pub fn from_files(files: Vec<PathBuf>) -> Result<AppConfig, CoreError> {
+        AppConfig::load(LoadOptions { files, ..LoadOptions::default() })
+    }
+
+    /// Loads the layers described by the options, see `LoadOptions`.
+    pub fn load(options: LoadOptions) -> Result<AppConfig, CoreError> {
+        let mut paths: Vec<PathBuf> = Vec::new();
+        for file in options.files {
+            let file = AppConfig::resolve_format(file);
+            if let Some(parent) = file.parent().filter(|p| !p.as_os_str().is_empty()) {
+                AppConfig::ensure_dir(&parent.to_path_buf())?;
+            }
+            AppConfig::ensure_file(&file)?;
+
+            let file = file.canonicalize()?;
+            if !paths.contains(&file) {
+                paths.push(file);
+            }
+        }
+
+        let top = match paths.last() {
+            Some(path) => path.clone(),
+            None => return Err(CoreError::for_app("No config file provided".to_string())),
+        };
+
+        debug!("Loading config files: {:?}", paths);
+        let mut config = AppConfig::new(top);
+
+        let mut known = Vec::new();
+        for path in paths.into_iter().rev() {
+            trace!("Creating config for machine: {:?}", path);
+            known.push(path.clone());
+            let machine_config = AppConfig::create_machine_config(path)?;
+            config.register_default_layer(ConfigTarget::Machine, machine_config)?;
+        }
+
+        let profile = options.profile
+            .or_else(|| config.provider.get::<String>(KnownConfigs::Profile.to_str()))
+            .filter(|name| !name.is_empty());
+        if let Some(name) = profile {
+            config.use_profile(&name)?;
+        }
 
-        config.provider.register_default(Config::Json(machine_config));
-        config.provider.register_default(cwd_config);
-        config.provider.register_default(repo_config);
+        trace!("Reading repository path");
+        let repo_configs = match config.resolve_repo_path()? {
+            Some(path) => {
+                trace!("Repository path: {:?}", &path.canonicalize());
+                let repo_configs = AppConfig::create_folder_configs(path.clone(), true)?;
+                let root = path.canonicalize().unwrap_or(path);
+                config.provider.set_variable("repo.root", root.display().to_string());
+                repo_configs
+            },
+            None => Vec::new(),
+        };
+
+        let dir = match options.dir {
+            Some(dir) => dir,
+            None => std::env::current_dir()?,
+        };
+        trace!("Creating configs from dir: {:?}", dir);
+        known.extend(repo_configs.iter()
+            .filter_map(|c| c.path())
+            .filter_map(|p| p.canonicalize().ok()));
+        let local_configs = AppConfig::create_local_configs(dir, &known)?;
+
+        for local_config in local_configs {
+            config.register_default_layer(ConfigTarget::Local, local_config)?;
+        }
+        for repo_config in repo_configs {
+            config.register_default_layer(ConfigTarget::Repo, repo_config)?;
+        }
 
         Ok(config)
     }
 
-    fn create_machine_config(path: PathBuf) -> Result<ConfigJson, CoreError> {
+    /// Registers a layer below the current ones, with the conditional
+    /// blocks that match this host right above it, see `overlay::matching`.
+    /// The blocks get the `Overlay` target, so reads and edits of the
+    /// layer's own target only see what its file stores.
+    fn register_default_layer(&mut self, target: ConfigTarget, config: Config) -> Result<(), CoreError> {
+        let overlays = AppConfig::create_overlays(&config, &self.host)?;
+        for overlay in overlays.into_iter().rev() {
+            self.provider.register_default_for(ConfigTarget::Overlay, overlay);
+        }
+        self.provider.register_default_for(target, config);
+        Ok(())
+    }
+
+    /// Registers a layer above the current ones, with its matching
+    /// conditional blocks above it.
+    fn register_top_layer(&mut self, target: ConfigTarget, config: Config) -> Result<(), CoreError> {
+        let overlays = AppConfig::create_overlays(&config, &self.host)?;
+        self.provider.register_top_for(target, config);
+        for overlay in overlays {
+            self.provider.register_top_for(ConfigTarget::Overlay, overlay);
+        }
+        Ok(())
+    }
+
+    fn create_overlays(config: &Config, host: &HostInfo) -> Result<Vec<Config>, CoreError> {
+        overlay::matching(config, host).into_iter()
+            .map(|value| {
+                trace!("Applying conditional block from {:?}", config.path());
+                ConfigJson::from_data(value.to_string())
+                    .map(Config::Json)
+                    .map_err(CoreError::for_err)
+            })
+            .collect()
+    }
+
+    /// Adds the layers of a profile above the machine config: the
+    /// `profiles.<name>` object of the machine config, and over it the
+    /// `profiles/<name>.json` (or `.toml`) file next to the machine config.
+    fn use_profile(&mut self, name: &str) -> Result<(), CoreError> {
+        if name.contains(['/', '\\', '.']) {
+            return Err(CoreError::for_app(format!("Invalid profile name: {}", name)));
+        }
+
+        debug!("Using profile: {}", name);
+        let mut found = false;
+        if let Some(value) = self.provider.get_merged(&format!("{}.{}", PROFILES_KEY, name)) {
+            if !value.is_object() {
+                return Err(CoreError::for_app(format!("Profile {} must be an object", name)));
+            }
+
+            let layer = ConfigJson::from_data(value.to_string()).map_err(CoreError::for_err)?;
+            self.register_top_layer(ConfigTarget::Profile, Config::Json(layer))?;
+            found = true;
+        }
+
+        let folder = self.root.parent().map(|p| p.join(PROFILES_KEY)).unwrap_or_default();
+        let file = AppConfig::resolve_format(folder.join(format!("{}.json", name)));
+        if file.is_file() {
+            trace!("Loading profile file: {:?}", file);
+            let layer = Config::from_file(file).map_err(CoreError::for_err)?;
+            self.register_top_layer(ConfigTarget::Profile, layer)?;
+            found = true;
+        }
+
+        match found {
+            true => Ok(()),
+            false => Err(CoreError::for_app(format!("Profile not found: {}", name))),
+        }
+    }
+
+    fn create_machine_config(path: PathBuf) -> Result<Config, CoreError> {
         AppConfig::ensure_file(&path)?;
-        match ConfigJson::from_file(path) {
+        match Config::from_file(path) {
             Err(e) => Err(CoreError::for_err(e)),
             Ok(cfg) => Ok(cfg),
         }
     }
 
-    fn create_repo_config(machine_config: &ConfigJson, root: &PathBuf) -> Result<Config, CoreError> {
-        trace!("Reading repository path");
-        let repo_var = machine_config.get::<String>(KnownConfigs::RepoPath.to_str());
-        let repo_path = AppConfig::internal_get_repo_path(repo_var, root);
-        match repo_path {
-            Some(path) => {
-                trace!("Repository path: {:?}", &path.canonicalize());
-                return AppConfig::create_folder_config(path, true)
-            },
-            None => Ok(Config::None),
+    /// Collects the folder configs from `path` up to the git root, or the
+    /// filesystem root when not inside a git work tree, nearest first.
+    /// Files listed in `skip` are already loaded as other layers.
+    fn create_local_configs(path: PathBuf, skip: &[PathBuf]) -> Result<Vec<Config>, CoreError> {
+        let mut configs = Vec::new();
+        for dir in path.ancestors() {
+            for config in AppConfig::create_folder_configs(dir.to_path_buf(), false)? {
+                let is_known = config.path()
+                    .and_then(|p| p.canonicalize().ok())
+                    .map(|p| skip.contains(&p))
+                    .unwrap_or(false);
+
+                if !is_known {
+                    trace!("Found local config: {:?}", config.path());
+                    configs.push(config);
+                }
+            }
+
+            if dir.join(".git").exists() {
+                break;
+            }
         }
+
+        Ok(configs)
     }
 
     fn create_folder_config(path: PathBuf, create: bool) -> Result<Config, CoreError> {
@@ -151,15 +524,52 @@ impl AppConfig {
         }
 
         AppConfig::ensure_dir(&path)?;
-        let folder_config_path = path.join(".config-sh.json");
+        let folder_config_path = AppConfig::resolve_format(path.join(".config-sh.json"));
         if !folder_config_path.exists() && !create {
             return Ok(Config::None);
         }
 
         AppConfig::ensure_file(&folder_config_path)?;
-        match ConfigJson::from_file(folder_config_path) {
+        match Config::from_file(folder_config_path) {
             Err(e) => Err(CoreError::for_err(e)),
-            Ok(cfg) => Ok(Config::Json(cfg)),
+            Ok(cfg) => Ok(cfg),
+        }
+    }
+
+    /// Collects the configs of a folder: `.config-sh.json` (created when
+    /// `create` is set) followed by the dotenv files that exist.
+    fn create_folder_configs(path: PathBuf, create: bool) -> Result<Vec<Config>, CoreError> {
+        let mut configs = Vec::new();
+        match AppConfig::create_folder_config(path.clone(), create)? {
+            Config::None => (),
+            config => configs.push(config),
+        }
+
+        for name in DOTENV_FILES {
+            let dotenv_path = path.join(name);
+            if dotenv_path.is_file() {
+                trace!("Found dotenv file: {:?}", dotenv_path);
+                match ConfigDotEnv::from_file(dotenv_path, Some(ENV_PREFIX.to_string())) {
+                    Err(e) => return Err(CoreError::for_err(e)),
+                    Ok(cfg) => configs.push(Config::DotEnv(cfg)),
+                }
+            }
+        }
+
+        Ok(configs)
+    }
+
+    /// Swaps a missing `.json` path for its `.toml` sibling when that exists.
+    fn resolve_format(path: PathBuf) -> PathBuf {
+        if path.exists() {
+            return path;
+        }
+
+        let toml_path = path.with_extension("toml");
+        if toml_path.exists() {
+            toml_path
+        } else {
+            path
         }
     }
 
@@ -170,12 +580,13 @@ impl AppConfig {
             Ok(exists) => {
                 if !exists {
                     trace!("Creating config file: {:?}", path.canonicalize());
-                    let _ = match File::create(path) {
-                        Err(e) => return Err(e.into()),
-                        Ok(file) => file,
+                    let _ = File::create(path)?;
+
+                    let empty = match path.extension().and_then(|e| e.to_str()) {
+                        Some("toml") => "",
+                        _ => "{}",
                     };
-                    
-                    match std::fs::write(path, "{}") {
+                    match std::fs::write(path, empty) {
                         Err(e) => return Err(e.into()),
                         Ok(_) => return Ok(()),
                     }
@@ -202,13 +613,41 @@ impl AppConfig {
         Ok(())
     }
 
-    fn internal_get_repo_path(path: Option<String>, root_file: &PathBuf) -> Option<PathBuf> {
-        match path.map(|p| PathBuf::from(p)) {
+    /// Moves the legacy machine config to the XDG location, see
+    /// `AppDirs::migrate_legacy_config`. A relative repository path is read
+    /// from the folder of the file that sets it, so it is made absolute
+    /// first to keep pointing at the same repository.
+    pub fn migrate_legacy_config(dirs: &AppDirs) -> Result<Option<(PathBuf, PathBuf)>, CoreError> {
+        dirs.migrate_legacy_config(AppConfig::anchor_repo_path)
+    }
+
+    fn anchor_repo_path(file: &Path) -> Result<(), CoreError> {
+        let key = KnownConfigs::RepoPath.to_str();
+        let mut config = Config::from_file(file.to_path_buf()).map_err(CoreError::for_err)?;
+        let path = match config.get_value(key) {
+            Some(Value::String(path)) if Path::new(&path).is_relative() => path,
+            _ => return Ok(()),
+        };
+
+        // The expanded value may or may not be relative, leave it to the user.
+        if interpolate::has_references(&path) {
+            return Err(CoreError::for_app(format!(
+                "{} in {} is relative to its folder and has references, set it to an absolute path before moving the file",
+                key, file.display())));
+        }
+
+        let absolute = AppConfig::internal_get_repo_path(Some(path), file).unwrap_or_default();
+        config.set_value(key, Value::String(absolute.display().to_string()));
+        config.save().map_err(CoreError::for_err)
+    }
+
+    fn internal_get_repo_path(path: Option<String>, root_file: &Path) -> Option<PathBuf> {
+        match path.map(PathBuf::from) {
             Some(path) => {
                 if path.is_relative() {
                     let root = match root_file.parent() {
                         Some(e) => e,
-                        None => &root_file
+                        None => root_file
                     };
                     Some(root.join(path))
                 } else {
@@ -219,13 +658,20 @@ impl AppConfig {
         }
     }
 
+    /// Returns the configured value, or the key default when none is set.
     pub fn get<T>(&self, key: KnownConfigs) -> Option<T> 
         where T: std::str::FromStr + serde::de::DeserializeOwned {
-        self.provider.get(key.to_str())
+        match self.provider.get(key.to_str()) {
+            Some(value) => Some(value),
+            None => key.schema().default_value()
+                .and_then(|v| serde_json::from_value(v).ok()),
+        }
     }
 
+    /// Returns the configured value, or the key default when none is set.
     pub fn get_value(&self, key: KnownConfigs) -> Option<Value> {
         self.provider.get_value(key.to_str())
+            .or_else(|| key.schema().default_value())
     }
 
     pub fn set<T>(&mut self, key: KnownConfigs, value: T)
@@ -237,13 +683,55 @@ impl AppConfig {
         self.provider.set_value(key.to_str(), value)
     }
 
+    pub fn get_in<T>(&self, target: ConfigTarget, key: KnownConfigs) -> Option<T>
+        where T: std::str::FromStr + serde::de::DeserializeOwned {
+        self.provider.get_in(target, key.to_str())
+    }
+
+    pub fn set_in<T>(&mut self, target: ConfigTarget, key: KnownConfigs, value: T) -> bool
+    where T: serde::Serialize {
+        self.provider.set_in(target, key.to_str(), value)
+    }
+
+    pub fn unset(&mut self, key: KnownConfigs) -> bool {
+        self.provider.unset(key.to_str())
+    }
+
+    pub fn unset_in(&mut self, target: ConfigTarget, key: KnownConfigs) -> bool {
+        self.provider.unset_in(target, key.to_str())
+    }
+
     pub fn get_repo_path(&self) -> Option<PathBuf> {
-        let path = self.get::<String>(KnownConfigs::RepoPath);
-        AppConfig::internal_get_repo_path(path, &self.root)
+        match self.resolve_repo_path() {
+            Ok(path) => path,
+            Err(e) => {
+                error!("Cannot read repository path: {}", e.message);
+                None
+            }
+        }
+    }
+
+    /// Resolves the repository path with its references expanded. A
+    /// relative path is read from the folder of the file that sets it.
+    fn resolve_repo_path(&self) -> Result<Option<PathBuf>, CoreError> {
+        let key = KnownConfigs::RepoPath;
+        let path = match self.provider.resolve_value(key.to_str())? {
+            Some(value) => {
+                key.schema().validate(&value)?;
+                value.as_str().map(|s| s.to_string())
+            },
+            None => return Ok(None),
+        };
+
+        let root = self.provider.get_origins(key.to_str()).into_iter()
+            .next()
+            .and_then(|o| o.path)
+            .unwrap_or_else(|| self.root.clone());
+        Ok(AppConfig::internal_get_repo_path(path, &root))
     }
 
     pub fn get_repo(&self) -> Result<Repo, CoreError> {
-        let repo_path = match self.get_repo_path() {
+        let repo_path = match self.resolve_repo_path()? {
             Some(e) => e,
             None => return Err(CoreError::for_app("Invalid path".to_string())),
         };
@@ -257,4 +745,256 @@ enum_str!(KnownConfigs {
     GitUser = "core.git.user",
     GitEmail = "core.git.email",
     LogLevel = "core.log.level",
-});
\ No newline at end of file
+    ScriptPaths = "core.script.paths",
+    Interpreters = "core.script.interpreters",
+    Profile = "core.profile",
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::testing::temp_dir;
+
+    /// Loads the files with local configs looked up from `dir`, so tests
+    /// don't depend on the folder they run from.
+    fn load_in(dir: &Path, files: Vec<PathBuf>) -> Result<AppConfig, CoreError> {
+        AppConfig::load(LoadOptions { files, dir: Some(dir.to_path_buf()), ..LoadOptions::default() })
+    }
+
+    #[test]
+    fn migrate_legacy_config_keeps_relative_repo_path() {
+        let home = temp_dir("config-legacy");
+        let dirs = AppDirs { home: home.clone(), config: home.join("xdg"), state: home.join("state"), cache: home.join("cache") };
+        std::fs::write(dirs.legacy_config_file(), r#"{ "core": { "repo": { "path": "repo" } } }"#).unwrap();
+
+        assert!(AppConfig::migrate_legacy_config(&dirs).unwrap().is_some());
+        let moved = Config::from_file(dirs.config_file()).unwrap();
+        assert_eq!(moved.get::<String>(KnownConfigs::RepoPath.to_str()), Some(home.join("repo").display().to_string()));
+
+        std::fs::write(dirs.legacy_config_file(), r#"{ "core": { "repo": { "path": "${env:REPO}" } } }"#).unwrap();
+        std::fs::remove_file(dirs.config_file()).unwrap();
+        assert!(AppConfig::migrate_legacy_config(&dirs).is_err());
+        assert!(dirs.legacy_config_file().exists());
+
+        std::fs::remove_dir_all(home).unwrap();
+    }
+
+    #[test]
+    fn create_local_configs_walks_up_to_git_root() {
+        let root = temp_dir("config-walk");
+        let project = root.join("project");
+        let nested = project.join("src").join("nested");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::create_dir_all(project.join(".git")).unwrap();
+        std::fs::write(root.join(".config-sh.json"), r#"{ "key": "outside" }"#).unwrap();
+        std::fs::write(project.join(".config-sh.json"), r#"{ "key": "project" }"#).unwrap();
+        std::fs::write(nested.join(".config-sh.json"), r#"{ "key": "nested" }"#).unwrap();
+
+        let configs = AppConfig::create_local_configs(nested, &[]).unwrap();
+        let values: Vec<Option<String>> = configs.iter().map(|c| c.get("key")).collect();
+        assert_eq!(values, vec![Some("nested".to_string()), Some("project".to_string())]);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn add_item_appends_to_array() {
+        let mut config = Config::Json(ConfigJson::from_data(r#"{ "list": ["a"], "single": "a" }"#.to_string()).unwrap());
+        assert!(config.add_item("list", Value::from("b")));
+        assert!(config.add_item("single", Value::from("b")));
+        assert!(config.add_item("new", Value::from(1)));
+        assert_eq!(config.get_value("list"), Some(serde_json::json!(["a", "b"])));
+        assert_eq!(config.get_value("single"), Some(serde_json::json!(["a", "b"])));
+        assert_eq!(config.get_value("new"), Some(serde_json::json!([1])));
+        assert!(!Config::None.add_item("list", Value::from("a")));
+    }
+
+    #[test]
+    fn remove_item_removes_matching_values() {
+        let mut config = Config::Json(ConfigJson::from_data(r#"{ "list": ["a", "b", "a"], "single": "a" }"#.to_string()).unwrap());
+        assert!(config.remove_item("list", &Value::from("a")));
+        assert!(!config.remove_item("list", &Value::from("c")));
+        assert!(config.remove_item("single", &Value::from("a")));
+        assert_eq!(config.get_value("list"), Some(serde_json::json!(["b"])));
+        assert_eq!(config.get_value("single"), None);
+    }
+
+    #[test]
+    fn parse_override_splits_key_and_value() {
+        let (key, value) = AppConfig::parse_override("core.log.level=INFO").unwrap();
+        assert_eq!(key, "core.log.level");
+        assert_eq!(value, Value::String("info".to_string()));
+
+        let (key, value) = AppConfig::parse_override("my.key=a=b").unwrap();
+        assert_eq!(key, "my.key");
+        assert_eq!(value, Value::String("a=b".to_string()));
+
+        assert!(AppConfig::parse_override("novalue").is_err());
+        assert!(AppConfig::parse_override("=value").is_err());
+    }
+
+    #[test]
+    fn use_overrides_adds_top_layer_that_is_not_saved() {
+        let mut config = AppConfig::new(PathBuf::from("config.json"));
+        let machine = ConfigJson::new(Box::new(crate::infra::io::ConfigData::new("{}".to_string())), true);
+        config.provider.register_default_for(ConfigTarget::Machine, Config::Json(machine));
+        config.use_overrides(vec![("core.log.level".to_string(), Value::String("info".to_string()))]).unwrap();
+
+        assert_eq!(config.get::<String>(KnownConfigs::LogLevel), Some("info".to_string()));
+        config.set(KnownConfigs::GitUser, "me");
+        assert_eq!(config.get_in::<String>(ConfigTarget::Machine, KnownConfigs::GitUser), Some("me".to_string()));
+        assert_eq!(config.get_in::<String>(ConfigTarget::Cli, KnownConfigs::GitUser), None);
+    }
+
+    #[test]
+    fn from_files_stacks_later_files_on_top() {
+        let root = temp_dir("config-stack");
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        let base = root.join("base.json");
+        let personal = root.join("personal.json");
+        std::fs::write(&base, r#"{ "core": { "git": { "user": "team", "email": "team@mail" } } }"#).unwrap();
+        std::fs::write(&personal, r#"{ "core": { "git": { "user": "me" } } }"#).unwrap();
+
+        let mut config = load_in(&root, vec![base.clone(), personal.clone(), base]).unwrap();
+        assert_eq!(config.get::<String>(KnownConfigs::GitUser), Some("me".to_string()));
+        assert_eq!(config.get::<String>(KnownConfigs::GitEmail), Some("team@mail".to_string()));
+        assert_eq!(config.provider.get_origins(KnownConfigs::GitUser.to_str()).len(), 2);
+
+        config.set(KnownConfigs::LogLevel, "info");
+        config.provider.sync();
+        assert!(std::fs::read_to_string(&personal).unwrap().contains("info"));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn from_files_expands_repo_path() {
+        let root = temp_dir("config-expand");
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        let machine = root.join("machine.json");
+        let content = serde_json::json!({
+            "test": { "root": root.display().to_string() },
+            "core": { "repo": { "path": "${config:test.root}/repo" } },
+        });
+        std::fs::write(&machine, content.to_string()).unwrap();
+        std::fs::create_dir_all(root.join("repo")).unwrap();
+        std::fs::write(root.join("repo").join(".config-sh.json"), r#"{ "scratch": "${repo.root}/scratch" }"#).unwrap();
+
+        let config = load_in(&root, vec![machine]).unwrap();
+        let repo = root.join("repo").canonicalize().unwrap();
+        assert_eq!(config.get_repo_path(), Some(root.join("repo")));
+        assert_eq!(config.provider.get::<String>("scratch"), Some(format!("{}/scratch", repo.display())));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn load_applies_profile_over_machine_config() {
+        let root = temp_dir("config-profile");
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::create_dir_all(root.join("profiles")).unwrap();
+        let machine = root.join("config.json");
+        std::fs::write(&machine, r#"{
+            "core": { "profile": "work", "git": { "user": "me", "email": "me@home" } },
+            "profiles": { "work": { "core": { "git": { "email": "me@work" } } } }
+        }"#).unwrap();
+        std::fs::write(root.join("profiles").join("personal.json"), r#"{ "core": { "git": { "user": "personal" } } }"#).unwrap();
+
+        let config = load_in(&root, vec![machine.clone()]).unwrap();
+        assert_eq!(config.get::<String>(KnownConfigs::GitEmail), Some("me@work".to_string()));
+        let origin = config.provider.get_origins(KnownConfigs::GitEmail.to_str()).remove(0);
+        assert_eq!(origin.target, Some(ConfigTarget::Profile));
+
+        let options = LoadOptions { files: vec![machine.clone()], profile: Some("personal".to_string()), dir: Some(root.clone()) };
+        let config = AppConfig::load(options).unwrap();
+        assert_eq!(config.get::<String>(KnownConfigs::GitUser), Some("personal".to_string()));
+        assert_eq!(config.get::<String>(KnownConfigs::GitEmail), Some("me@home".to_string()));
+
+        let options = LoadOptions { files: vec![machine], profile: Some("missing".to_string()), dir: Some(root.clone()) };
+        assert!(AppConfig::load(options).is_err());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn load_applies_matching_conditional_blocks() {
+        let root = temp_dir("config-overlay");
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        let machine = root.join("config.json");
+        std::fs::write(&machine, format!(r#"{{
+            "core": {{ "git": {{ "user": "base", "email": "base@mail" }} }},
+            "overlays": [
+                {{ "when": {{ "os": "{}" }}, "core": {{ "git": {{ "user": "os" }} }} }},
+                {{ "when": {{ "host": "no-such-host-*" }}, "core": {{ "git": {{ "email": "host@mail" }} }} }}
+            ]
+        }}"#, std::env::consts::OS)).unwrap();
+
+        let mut config = load_in(&root, vec![machine.clone()]).unwrap();
+        assert_eq!(config.get::<String>(KnownConfigs::GitUser), Some("os".to_string()));
+        assert_eq!(config.get::<String>(KnownConfigs::GitEmail), Some("base@mail".to_string()));
+
+        config.set(KnownConfigs::GitEmail, "new@mail");
+        config.provider.sync();
+        assert!(std::fs::read_to_string(&machine).unwrap().contains("new@mail"));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn overlays_are_kept_apart_from_their_file() {
+        let root = temp_dir("config-overlay-target");
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        let machine = root.join("config.json");
+        std::fs::write(&machine, format!(r#"{{
+            "core": {{ "git": {{ "user": "base" }} }},
+            "overlays": [ {{ "when": {{ "os": "{}" }}, "core": {{ "git": {{ "user": "os" }} }} }} ]
+        }}"#, std::env::consts::OS)).unwrap();
+
+        let mut config = load_in(&root, vec![machine.clone()]).unwrap();
+        let stored = config.provider.layer_values(Some(ConfigTarget::Machine));
+        assert_eq!(stored["core"]["git"]["user"], "base");
+        assert_eq!(stored["overlays"].as_array().map(|o| o.len()), Some(1));
+        assert_eq!(
+            config.provider.layer_values(Some(ConfigTarget::Overlay)),
+            serde_json::json!({ "core": { "git": { "user": "os" } } })
+        );
+        assert_eq!(config.get_in::<String>(ConfigTarget::Machine, KnownConfigs::GitUser), Some("base".to_string()));
+
+        assert!(config.unset_in(ConfigTarget::Machine, KnownConfigs::GitUser));
+        assert!(!config.unset_in(ConfigTarget::Overlay, KnownConfigs::GitUser));
+        assert_eq!(config.get::<String>(KnownConfigs::GitUser), Some("os".to_string()));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn from_files_requires_a_file() {
+        assert!(AppConfig::from_files(vec![]).is_err());
+    }
+
+    #[test]
+    fn create_local_configs_reads_dotenv_files() {
+        let root = temp_dir("config-dotenv");
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::write(root.join(".config-sh.json"), r#"{ "core": { "git": { "user": "json" } } }"#).unwrap();
+        std::fs::write(root.join(".config-sh.env"), "SH_CORE_GIT_USER=sh-env\nSH_CORE_GIT_EMAIL=me@mail").unwrap();
+        std::fs::write(root.join(".env"), "SH_CORE_LOG_LEVEL=info\nOTHER=1").unwrap();
+
+        let configs = AppConfig::create_local_configs(root.clone(), &[]).unwrap();
+        assert_eq!(configs.len(), 3);
+        assert!(matches!(configs[0], Config::Json(_)));
+        assert_eq!(configs[1].get_value("core.git.email"), Some(Value::String("me@mail".to_string())));
+        assert_eq!(configs[2].get_value("core.log.level"), Some(Value::String("info".to_string())));
+        assert_eq!(configs[2].get_value("other"), None);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn create_local_configs_skips_known_files() {
+        let root = temp_dir("config-skip");
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::write(root.join(".config-sh.json"), "{}").unwrap();
+
+        let known = vec![root.join(".config-sh.json").canonicalize().unwrap()];
+        let configs = AppConfig::create_local_configs(root.clone(), &known).unwrap();
+        assert!(configs.is_empty());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
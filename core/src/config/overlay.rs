@@ -0,0 +1,111 @@
+use log::warn;
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::infra::host::HostInfo;
+use crate::infra::wildcard;
+
+/// Key of the conditional blocks in a config file.
+pub const OVERLAYS_KEY: &str = "overlays";
+
+/// Returns the values of the conditional blocks that apply to the host,
+/// in file order, without their `when` condition.
+///
+/// Blocks are listed under `overlays`, each with a `when` object that can
+/// check `os`, `arch` and `host` against a wildcard pattern or a list of
+/// them, for example `{ "when": { "os": "linux", "host": "build-*" },
+/// "core": { ... } }`. Every condition given has to match.
+pub fn matching(config: &Config, host: &HostInfo) -> Vec<Value> {
+    let blocks = match config.get_value(OVERLAYS_KEY) {
+        Some(Value::Array(blocks)) => blocks,
+        Some(_) => {
+            warn!("Ignoring {} in {:?}, expected a list of blocks", OVERLAYS_KEY, config.path());
+            return Vec::new();
+        },
+        None => return Vec::new(),
+    };
+
+    blocks.into_iter()
+        .filter_map(|block| match block {
+            Value::Object(mut block) => {
+                let when = block.remove("when").unwrap_or(Value::Null);
+                match matches(&when, host) {
+                    true => Some(Value::Object(block)),
+                    false => None,
+                }
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+fn matches(when: &Value, host: &HostInfo) -> bool {
+    let conditions = match when {
+        Value::Object(conditions) => conditions,
+        Value::Null => return true,
+        _ => return false,
+    };
+
+    conditions.iter().all(|(name, patterns)| {
+        let actual = match name.as_str() {
+            "os" => &host.os,
+            "arch" => &host.arch,
+            "host" => &host.host,
+            _ => {
+                warn!("Unknown condition in {} block: {}", OVERLAYS_KEY, name);
+                return false;
+            },
+        };
+
+        let matches_pattern = |pattern: &Value| pattern.as_str()
+            .map(|p| wildcard::matches(&p.to_lowercase(), &actual.to_lowercase()))
+            .unwrap_or(false);
+
+        match patterns {
+            Value::Array(patterns) => patterns.iter().any(matches_pattern),
+            pattern => matches_pattern(pattern),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config_json::ConfigJson;
+    use serde_json::json;
+
+    fn host() -> HostInfo {
+        HostInfo { os: "linux".to_string(), arch: "x86_64".to_string(), host: "build-01".to_string() }
+    }
+
+    #[test]
+    fn matches_checks_every_condition() {
+        assert!(matches(&json!({ "os": "linux", "host": "build-*" }), &host()));
+        assert!(matches(&json!({ "os": ["macos", "linux"], "arch": "x86_64" }), &host()));
+        assert!(matches(&json!({ "host": "BUILD-0?" }), &host()));
+        assert!(matches(&Value::Null, &host()));
+        assert!(!matches(&json!({ "os": "linux", "host": "dev-*" }), &host()));
+        assert!(!matches(&json!({ "kernel": "6" }), &host()));
+    }
+
+    #[test]
+    fn matching_returns_blocks_without_condition() {
+        let config = Config::Json(ConfigJson::from_data(r#"{ "overlays": [
+            { "when": { "os": "linux" }, "core": { "git": { "user": "linux" } } },
+            { "when": { "os": "windows" }, "core": { "git": { "user": "windows" } } },
+            { "when": { "host": "build-*" }, "key": 1 }
+        ] }"#.to_string()).unwrap());
+
+        assert_eq!(matching(&config, &host()), vec![
+            json!({ "core": { "git": { "user": "linux" } } }),
+            json!({ "key": 1 }),
+        ]);
+    }
+
+    #[test]
+    fn matching_ignores_invalid_overlays() {
+        let config = Config::Json(ConfigJson::from_data(r#"{ "overlays": { "os": "linux" } }"#.to_string()).unwrap());
+        assert!(matching(&config, &host()).is_empty());
+        assert!(matching(&Config::None, &host()).is_empty());
+    }
+}
@@ -1,19 +1,87 @@
+use std::collections::HashMap;
 use std::error::Error;
+use std::path::PathBuf;
 
+use log::error;
+use serde_json::Value;
+
+use crate::infra::error::CoreError;
 use crate::infra::priority_provider::{PriorityProvider, PriorityProviderIterator};
-use crate::config::Config;
+use crate::infra::wildcard;
+use crate::config::{Config, ConfigTarget};
+use crate::config::history::History;
+use crate::config::interpolate::{self, Reference};
+use crate::config::key_path;
+use crate::config::merge::{self, MergePolicy};
+use crate::config::schema;
+use crate::config::secret::{self, Secrets};
 
 pub struct ConfigProvider {
     providers: PriorityProvider<Config>,
+    targets: HashMap<i64, ConfigTarget>,
+    variables: HashMap<String, String>,
+    history: Option<History>,
+    secrets: Option<Secrets>,
+    mask_secrets: bool,
+}
+
+/// A value found for a key, and the layer it was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigOrigin {
+    pub value: Value,
+    pub target: Option<ConfigTarget>,
+    pub path: Option<PathBuf>,
+    pub priority: i64,
+}
+
+impl Default for ConfigProvider {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ConfigProvider {
     pub fn new() -> ConfigProvider {
         ConfigProvider {
             providers: PriorityProvider::new(),
+            targets: HashMap::new(),
+            variables: HashMap::new(),
+            history: None,
+            secrets: None,
+            mask_secrets: false,
         }
     }
 
+    /// Records the changes saved by `sync` in the history journal.
+    pub fn use_history(&mut self, history: History) {
+        self.history = Some(history);
+    }
+
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    /// Decrypts secret values on read and encrypts them on `set_secret`.
+    pub fn use_secrets(&mut self, secrets: Secrets) {
+        self.secrets = Some(secrets);
+    }
+
+    /// Reads secret values as `secret::MASK` instead of decrypting them,
+    /// for output that is shown to the user.
+    pub fn mask_secrets(&mut self, mask: bool) {
+        self.mask_secrets = mask;
+    }
+
+    /// Whether any layer holds the key, or a value under it, as a secret.
+    pub fn is_secret(&self, key: &str) -> bool {
+        self.get_origins(key).iter().any(|origin| secret::contains_secret(&origin.value))
+    }
+
+    /// Sets a value for `${name}` references, such as `repo.root`.
+    pub fn set_variable(&mut self, name: &str, value: String) {
+        self.variables.insert(name.to_string(), value);
+    }
+
     pub fn register_top(&mut self, config: Config) {
         self.providers.add_top(config);
     }
@@ -22,60 +90,526 @@ impl ConfigProvider {
         self.providers.add(config);
     }
 
-    pub fn iter(&self) -> PriorityProviderIterator<Config> {
+    pub fn register_top_for(&mut self, target: ConfigTarget, config: Config) {
+        let pos = self.providers.add_top(config);
+        self.targets.insert(pos, target);
+    }
+
+    pub fn register_default_for(&mut self, target: ConfigTarget, config: Config) {
+        let pos = self.providers.add(config);
+        self.targets.insert(pos, target);
+    }
+
+    /// Returns the target a layer was registered for, if any.
+    pub fn get_target(&self, pos: i64) -> Option<ConfigTarget> {
+        self.targets.get(&pos).copied()
+    }
+
+    pub fn iter(&self) -> PriorityProviderIterator<'_, Config> {
         self.providers.iter()
     }
 
+    /// Reads the key as `T` from the first layer whose value converts,
+    /// so a value of another type falls through to lower layers.
     pub fn get<T>(&self, key: &str) -> Option<T> 
     where T: std::str::FromStr + serde::de::DeserializeOwned {
-        self.providers.map_first(&|p| p.get(key))
+        self.first_converted(self.providers.priorities(), key)
     }
 
-    pub fn get_value(&self, key: &str) -> Option<serde_json::Value> {
-        self.providers.map_first(&|p| p.get_value(key))
+    /// Returns the value with its references expanded. Resolve errors are
+    /// logged and read as a missing value, use `resolve_value` to get them.
+    pub fn get_value(&self, key: &str) -> Option<Value> {
+        log_error(key, self.resolve_value(key))
+    }
+
+    /// Returns the value with `${env:NAME}`, `${config:key}` and `${name}`
+    /// references expanded, or an error if one can't be resolved.
+    pub fn resolve_value(&self, key: &str) -> Result<Option<Value>, CoreError> {
+        self.resolve_key(key, &mut Vec::new())
     }
     
+    /// Writes to the first layer that can be saved, or to the first
+    /// editable layer when none can.
     pub fn set<T>(&mut self, key: &str, value: T) 
     where T: serde::Serialize {
-        self.providers.first_mut(&|p| match p {
-            Config::Json(_) => true,
-            _ => false,
-        }).map(|p| match p {
-            Config::Json(src) => src.set(key, value),
-            _ => (),
-        });
+        let pos = self.first_editable(self.providers.priorities());
+        if let Some(config) = pos.and_then(|pos| self.providers.get_at_mut(pos)) {
+            config.set(key, value);
+        }
+    }
+
+    /// Writes to the first layer that can be saved, or to the first
+    /// editable layer when none can.
+    pub fn set_value(&mut self, key: &str, value: Value) {
+        let pos = self.first_editable(self.providers.priorities());
+        if let Some(config) = pos.and_then(|pos| self.providers.get_at_mut(pos)) {
+            config.set_value(key, value);
+        }
+    }
+
+    /// Encrypts the value and writes it like `set_value`.
+    pub fn set_secret(&mut self, key: &str, value: Value) -> Result<(), CoreError> {
+        let value = self.encrypt(&value)?;
+        self.set_value(key, value);
+        Ok(())
+    }
+
+    /// Encrypts the value and writes it like `set_value_in`.
+    pub fn set_secret_in(&mut self, target: ConfigTarget, key: &str, value: Value) -> Result<bool, CoreError> {
+        let value = self.encrypt(&value)?;
+        Ok(self.set_value_in(target, key, value))
+    }
+
+    /// Returns the key merged across every layer that defines it, see
+    /// `merge::merge`. Arrays follow the merge policy of the key schema.
+    pub fn get_merged(&self, key: &str) -> Option<Value> {
+        log_error(key, self.resolve_merged(key, schema::merge_policy(key)))
     }
 
-    pub fn set_value(&mut self, key: &str, value: serde_json::Value) {
-        self.providers.first_mut(&|p| match p {
-            Config::Json(_) => true,
-            _ => false,
-        }).map(|p| match p {
-            Config::Json(src) => src.set_value(key, value),
-            _ => (),
+    /// Like `get_merged`, with an explicit policy for arrays.
+    pub fn get_merged_with(&self, key: &str, policy: MergePolicy) -> Option<Value> {
+        log_error(key, self.resolve_merged(key, policy))
+    }
+
+    /// Merges the key across layers, from the lowest priority up, then
+    /// expands its references.
+    pub fn resolve_merged(&self, key: &str, policy: MergePolicy) -> Result<Option<Value>, CoreError> {
+        match self.merge_in(self.providers.priorities(), key, policy) {
+            Some(value) => self.resolve(value, &mut vec![key.to_string()]).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns every layer that defines the key, from top to bottom.
+    /// The first entry is the effective value, the rest are shadowed by it.
+    pub fn get_origins(&self, key: &str) -> Vec<ConfigOrigin> {
+        self.providers.priorities().into_iter()
+            .filter_map(|pos| {
+                let config = self.providers.get_at(pos)?;
+                let value = config.get_value(key)?;
+                Some(ConfigOrigin {
+                    value,
+                    target: self.get_target(pos),
+                    path: config.path(),
+                    priority: pos,
+                })
+            })
+            .collect()
+    }
+
+    /// Lists every key defined in any layer, sorted, with its effective
+    /// value and the layer it comes from. Values are merged and expanded
+    /// like `get_merged`; a value that fails to resolve is kept as written.
+    pub fn entries(&self) -> Vec<(String, ConfigOrigin)> {
+        let mut keys: Vec<String> = self.providers.iter().flat_map(|p| p.keys()).collect();
+        keys.sort();
+        keys.dedup();
+
+        keys.into_iter()
+            .filter_map(|key| {
+                let mut origin = self.get_origins(&key).into_iter().next()?;
+                if let Ok(Some(value)) = self.resolve_merged(&key, schema::merge_policy(&key)) {
+                    origin.value = value;
+                }
+                Some((key, origin))
+            })
+            .collect()
+    }
+
+    /// Returns the values stored in the layers of the target, or the
+    /// effective values of every layer when None, as one object. Keys are
+    /// merged like `get_merged`, but references and secrets are kept as
+    /// written.
+    pub fn layer_values(&self, target: Option<ConfigTarget>) -> Value {
+        let positions = match target {
+            Some(target) => self.target_priorities(target),
+            None => self.providers.priorities(),
+        };
+
+        let mut keys: Vec<String> = positions.iter()
+            .filter_map(|pos| self.providers.get_at(*pos))
+            .flat_map(|p| p.keys())
+            .collect();
+        keys.sort();
+        keys.dedup();
+
+        let mut values = Value::Object(serde_json::Map::new());
+        for key in keys {
+            if let Some(value) = self.merge_in(positions.clone(), &key, schema::merge_policy(&key)) {
+                key_path::set(&mut values, &key, value);
+            }
+        }
+        values
+    }
+
+    /// Writes every value of the object to the first layer that can be
+    /// saved, in the target when given. With `replace` the keys already in
+    /// that layer are removed first, except secrets: exports leave them
+    /// out, so a missing secret is not a removal. Keys held as secrets stay
+    /// encrypted. Returns false if there is no such layer.
+    pub fn import(&mut self, target: Option<ConfigTarget>, values: &Value, replace: bool) -> Result<bool, CoreError> {
+        let mut entries = Vec::new();
+        for key in key_path::keys(values) {
+            let value = match key_path::get(values, &key) {
+                Some(value) => value.clone(),
+                None => continue,
+            };
+
+            let value = match self.is_secret(&key) && !secret::contains_secret(&value) {
+                true => self.encrypt(&value)?,
+                false => value,
+            };
+            entries.push((key, value));
+        }
+
+        let positions = match target {
+            Some(target) => self.target_priorities(target),
+            None => self.providers.priorities(),
+        };
+        let pos = positions.into_iter().find(|pos| {
+            self.providers.get_at(*pos).map(|p| p.is_editable() && p.can_write()).unwrap_or(false)
         });
+        let config = match pos.and_then(|pos| self.providers.get_at_mut(pos)) {
+            Some(config) => config,
+            None => return Ok(false),
+        };
+
+        if replace {
+            for key in config.keys() {
+                let is_secret = config.get_value(&key).map(|v| secret::contains_secret(&v)).unwrap_or(false);
+                if !is_secret {
+                    config.unset(&key);
+                }
+            }
+        }
+
+        for (key, value) in entries {
+            config.set_value(&key, value);
+        }
+        Ok(true)
+    }
+
+    /// Returns the entries under a namespace: the key itself and every
+    /// key below it (`core.git` gives `core.git.user`, `core.git.email`).
+    pub fn get_prefix(&self, prefix: &str) -> Vec<(String, ConfigOrigin)> {
+        let prefix = prefix.trim_end_matches('.');
+        let namespace = format!("{}.", prefix);
+        self.entries().into_iter()
+            .filter(|(key, _)| key == prefix || key.starts_with(&namespace))
+            .collect()
+    }
+
+    /// Returns the entries whose key matches a wildcard pattern, see
+    /// `wildcard::matches`.
+    pub fn get_matching(&self, pattern: &str) -> Vec<(String, ConfigOrigin)> {
+        self.entries().into_iter()
+            .filter(|(key, _)| wildcard::matches(pattern, key))
+            .collect()
     }
 
+    pub fn get_in<T>(&self, target: ConfigTarget, key: &str) -> Option<T>
+    where T: std::str::FromStr + serde::de::DeserializeOwned {
+        self.first_converted(self.target_priorities(target), key)
+    }
+
+    pub fn get_value_in(&self, target: ConfigTarget, key: &str) -> Option<Value> {
+        log_error(key, self.resolve_value_in(target, key))
+    }
+
+    /// Like `resolve_value`, reading only the layers of the target.
+    /// References to other keys still resolve against every layer.
+    pub fn resolve_value_in(&self, target: ConfigTarget, key: &str) -> Result<Option<Value>, CoreError> {
+        let value = self.target_priorities(target).into_iter()
+            .filter_map(|pos| self.providers.get_at(pos))
+            .find_map(|p| p.get_value(key));
+
+        match value {
+            Some(value) => self.resolve(value, &mut vec![key.to_string()]).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Writes to the first editable layer registered for the target,
+    /// preferring layers that can be saved.
+    /// Returns false if there is no such layer.
+    pub fn set_in<T>(&mut self, target: ConfigTarget, key: &str, value: T) -> bool
+    where T: serde::Serialize {
+        match self.first_editable_in(target) {
+            Some(config) => config.set(key, value),
+            None => false,
+        }
+    }
+
+    /// Writes to the first editable layer registered for the target,
+    /// preferring layers that can be saved.
+    /// Returns false if there is no such layer.
+    pub fn set_value_in(&mut self, target: ConfigTarget, key: &str, value: Value) -> bool {
+        match self.first_editable_in(target) {
+            Some(config) => config.set_value(key, value),
+            None => false,
+        }
+    }
+
+    /// Appends an item to the array at the key in the layer `set` writes to.
+    /// Returns false if there is no editable layer, or if the key is a
+    /// secret, which can only be replaced as a whole with `set_secret`.
+    pub fn add_value(&mut self, key: &str, item: Value) -> bool {
+        if self.is_secret(key) {
+            return false;
+        }
+
+        let pos = self.first_editable(self.providers.priorities());
+        match pos.and_then(|pos| self.providers.get_at_mut(pos)) {
+            Some(config) => config.add_item(key, item),
+            None => false,
+        }
+    }
+
+    /// Appends an item to the array at the key in the target, see `set_in`
+    /// and `add_value`.
+    pub fn add_value_in(&mut self, target: ConfigTarget, key: &str, item: Value) -> bool {
+        if self.is_secret(key) {
+            return false;
+        }
+
+        match self.first_editable_in(target) {
+            Some(config) => config.add_item(key, item),
+            None => false,
+        }
+    }
+
+    /// Removes the item from the key in every layer that can be saved,
+    /// returns true if any layer had it.
+    pub fn remove_value(&mut self, key: &str, item: &Value) -> bool {
+        self.providers.map_mut(&|p| Some(p.can_write() && p.remove_item(key, item)))
+            .into_iter()
+            .any(|removed| removed)
+    }
+
+    /// Removes the item from the key in every layer of the target that
+    /// can be saved.
+    pub fn remove_value_in(&mut self, target: ConfigTarget, key: &str, item: &Value) -> bool {
+        let mut removed = false;
+        for pos in self.target_priorities(target) {
+            match self.providers.get_at_mut(pos) {
+                Some(config) if config.can_write() => removed |= config.remove_item(key, item),
+                _ => (),
+            }
+        }
+        removed
+    }
+
+    /// Removes the key from every layer that can be saved, returns true if
+    /// any layer had it. Profile, overlay and override layers are kept,
+    /// see `get_origins` for what still defines the key.
+    pub fn unset(&mut self, key: &str) -> bool {
+        self.providers.map_mut(&|p| Some(p.can_write() && p.unset(key)))
+            .into_iter()
+            .any(|removed| removed)
+    }
+
+    /// Removes the key from every layer registered for the target that can
+    /// be saved, returns true if any of them had it.
+    pub fn unset_in(&mut self, target: ConfigTarget, key: &str) -> bool {
+        let mut removed = false;
+        for pos in self.target_priorities(target) {
+            match self.providers.get_at_mut(pos) {
+                Some(config) if config.can_write() => removed |= config.unset(key),
+                _ => (),
+            }
+        }
+        removed
+    }
+
+    fn resolve_key(&self, key: &str, stack: &mut Vec<String>) -> Result<Option<Value>, CoreError> {
+        if stack.iter().any(|k| k == key) {
+            stack.push(key.to_string());
+            return Err(CoreError::for_app(format!("Cycle in config references: {}", stack.join(" -> "))));
+        }
+
+        let value = match self.providers.map_first(&|p| p.get_value(key)) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        stack.push(key.to_string());
+        let value = self.resolve(value, stack)?;
+        stack.pop();
+        Ok(Some(value))
+    }
+
+    /// Expands the references in every string of the value and decrypts
+    /// its secrets.
+    fn resolve(&self, value: Value, stack: &mut Vec<String>) -> Result<Value, CoreError> {
+        match value {
+            Value::String(text) if secret::is_secret(&text) => self.reveal(&text),
+            Value::String(text) if interpolate::has_references(&text) => {
+                let text = interpolate::expand(&text, |reference| self.lookup(reference, stack))?;
+                Ok(Value::String(text))
+            },
+            Value::Array(items) => items.into_iter()
+                .map(|item| self.resolve(item, stack))
+                .collect::<Result<Vec<Value>, CoreError>>()
+                .map(Value::Array),
+            Value::Object(map) => map.into_iter()
+                .map(|(k, v)| self.resolve(v, stack).map(|v| (k, v)))
+                .collect::<Result<serde_json::Map<String, Value>, CoreError>>()
+                .map(Value::Object),
+            value => Ok(value),
+        }
+    }
+
+    fn lookup(&self, reference: Reference, stack: &mut Vec<String>) -> Result<String, CoreError> {
+        match reference {
+            Reference::Env(name) => std::env::var(name)
+                .map_err(|_| CoreError::for_app(format!("Environment variable {} is not set", name))),
+            Reference::Config(key) => match self.resolve_key(key, stack)? {
+                Some(Value::String(text)) => Ok(text),
+                Some(value @ (Value::Number(_) | Value::Bool(_))) => Ok(value.to_string()),
+                Some(_) => Err(CoreError::for_app(format!("Config reference {} is not a single value", key))),
+                None => Err(CoreError::for_app(format!("No value found for config reference: {}", key))),
+            },
+            Reference::Var(name) => self.variables.get(name).cloned()
+                .ok_or_else(|| CoreError::for_app(format!("Unknown variable: {}", name))),
+        }
+    }
+
+    /// Merges the key across the layers at the positions, from the lowest
+    /// priority up, without expanding it.
+    fn merge_in(&self, positions: Vec<i64>, key: &str, policy: MergePolicy) -> Option<Value> {
+        positions.into_iter().rev()
+            .filter_map(|pos| self.providers.get_at(pos))
+            .filter_map(|p| p.get_value(key))
+            .reduce(|lower, upper| merge::merge(lower, upper, policy))
+    }
+
+    fn reveal(&self, text: &str) -> Result<Value, CoreError> {
+        if self.mask_secrets {
+            return Ok(Value::String(secret::MASK.to_string()));
+        }
+
+        match &self.secrets {
+            Some(secrets) => secrets.decrypt(text),
+            None => Err(CoreError::for_app("Secrets are not available".to_string())),
+        }
+    }
+
+    fn encrypt(&self, value: &Value) -> Result<Value, CoreError> {
+        match &self.secrets {
+            Some(secrets) => secrets.encrypt(value),
+            None => Err(CoreError::for_app("Secrets are not available".to_string())),
+        }
+    }
+
+    fn first_converted<T>(&self, positions: Vec<i64>, key: &str) -> Option<T>
+    where T: std::str::FromStr + serde::de::DeserializeOwned {
+        positions.into_iter()
+            .filter_map(|pos| self.providers.get_at(pos))
+            .filter_map(|p| p.get_value(key))
+            .find_map(|value| {
+                let value = self.resolve(value, &mut vec![key.to_string()]).map(Some);
+                log_error(key, value).and_then(convert)
+            })
+    }
+
+    fn target_priorities(&self, target: ConfigTarget) -> Vec<i64> {
+        self.providers.priorities().into_iter()
+            .filter(|pos| self.targets.get(pos) == Some(&target))
+            .collect()
+    }
+
+    fn first_editable_in(&mut self, target: ConfigTarget) -> Option<&mut Config> {
+        let pos = self.first_editable(self.target_priorities(target))?;
+        self.providers.get_at_mut(pos)
+    }
+
+    fn first_editable(&self, positions: Vec<i64>) -> Option<i64> {
+        let layers: Vec<(i64, &Config)> = positions.into_iter()
+            .filter_map(|pos| self.providers.get_at(pos).map(|p| (pos, p)))
+            .filter(|(_, p)| p.is_editable())
+            .collect();
+
+        layers.iter().find(|(_, p)| p.can_write())
+            .or(layers.first())
+            .map(|(pos, _)| *pos)
+    }
+
+    /// Marks the migrations applied on load to be written by the next
+    /// `sync`, returns true if any layer had some.
+    pub fn stage_migrations(&mut self) -> bool {
+        self.providers.map_mut(&|p| Some(p.can_write() && p.stage_migrations()))
+            .into_iter()
+            .any(|staged| staged)
+    }
+
+    /// Saves every layer with pending changes, recording the changed keys
+    /// in the history when one is set.
     pub fn sync(&mut self) -> Vec<Result<(), Box<dyn Error>>> {
-        self.providers.map_mut(&|p| match p {
-            Config::Json(src) => {
-                if !src.is_synced() && src.can_write() {
-                    Some(src.save())
-                } else {
-                    None
-                }
+        let mut results = Vec::new();
+        let mut entries = Vec::new();
+        for pos in self.providers.priorities() {
+            let target = self.get_target(pos);
+            let config = match self.providers.get_at_mut(pos) {
+                Some(config) if config.needs_save() => config,
+                _ => continue,
+            };
+
+            let changes = config.changes();
+            let result = config.save();
+            if let (Ok(_), Some(history)) = (&result, &self.history) {
+                entries.extend(changes.into_iter()
+                    .map(|(key, old, new)| history.entry(key, target, config.path(), old, new)));
             }
-            _ => None,
-        })
+            results.push(result);
+        }
+
+        if let Some(history) = &self.history {
+            if let Err(e) = history.append(&entries) {
+                results.push(Err(format!("Cannot write config history: {}", e.message).into()));
+            }
+        }
+
+        results
+    }
+}
+
+/// Reads a value as `T`, parsing strings when they don't deserialize
+/// directly, so env values such as `"10"` still read as numbers.
+fn convert<T>(value: Value) -> Option<T>
+where T: std::str::FromStr + serde::de::DeserializeOwned {
+    match value {
+        Value::String(text) => serde_json::from_value(Value::String(text.clone())).ok()
+            .or_else(|| text.parse::<T>().ok()),
+        value => serde_json::from_value(value).ok(),
+    }
+}
+
+fn log_error(key: &str, result: Result<Option<Value>, CoreError>) -> Option<Value> {
+    match result {
+        Ok(value) => value,
+        Err(e) => {
+            error!("Cannot read config {}: {}", key, e.message);
+            None
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::config::config_json::ConfigJson;
+    use crate::infra::io::ConfigData;
+    use crate::config::migrate::SCHEMA_VERSION_KEY;
+    use crate::infra::testing::temp_dir;
 
     use super::*;
 
+    /// A JSON layer that can be saved, backed by memory.
+    fn writable(data: &str) -> Config {
+        let mut config = Config::Json(ConfigJson::new(Box::new(ConfigData::new(data.to_string())), true));
+        config.load().unwrap();
+        config
+    }
+
     #[test]
     fn get_return_first_match() {
         let config1 = ConfigJson::from_data(r#"{ "key": "value_1" }"#.to_string()).unwrap();
@@ -91,6 +625,21 @@ mod tests {
         assert_eq!(value, Some("value_3".to_string()));
     }
 
+    #[test]
+    fn get_falls_through_values_of_another_type() {
+        let config1 = ConfigJson::from_data(r#"{ "key": "many" }"#.to_string()).unwrap();
+        let config2 = ConfigJson::from_data(r#"{ "key": 3 }"#.to_string()).unwrap();
+
+        let mut provider = ConfigProvider::new();
+        provider.register_default_for(ConfigTarget::Machine, Config::Json(config1));
+        provider.register_default_for(ConfigTarget::Machine, Config::Json(config2));
+
+        assert_eq!(provider.get::<String>("key"), Some("many".to_string()));
+        assert_eq!(provider.get::<i64>("key"), Some(3));
+        assert_eq!(provider.get_in::<i64>(ConfigTarget::Machine, "key"), Some(3));
+        assert_eq!(provider.get::<bool>("key"), None);
+    }
+
     #[test]
     fn get_skip_invalid_match() {
         let config1 = ConfigJson::from_data(r#"{ "key": "value_1" }"#.to_string()).unwrap();
@@ -130,4 +679,347 @@ mod tests {
         let value = provider.get::<String>("key");
         assert_eq!(value, Some("value_4".to_string()));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn get_origins_lists_shadowed_values() {
+        let config1 = ConfigJson::from_data(r#"{ "key": "value_1" }"#.to_string()).unwrap();
+        let config2 = ConfigJson::from_data(r#"{ "other": "value" }"#.to_string()).unwrap();
+        let config3 = ConfigJson::from_data(r#"{ "key": "value_3" }"#.to_string()).unwrap();
+
+        let mut provider = ConfigProvider::new();
+        provider.register_default_for(ConfigTarget::Machine, Config::Json(config1));
+        provider.register_default_for(ConfigTarget::Local, Config::Json(config2));
+        provider.register_top(Config::Json(config3));
+
+        let origins = provider.get_origins("key");
+        assert_eq!(origins.len(), 2);
+        assert_eq!(origins[0].value, "value_3");
+        assert_eq!(origins[0].target, None);
+        assert_eq!(origins[0].priority, -1);
+        assert_eq!(origins[1].value, "value_1");
+        assert_eq!(origins[1].target, Some(ConfigTarget::Machine));
+        assert_eq!(origins[1].priority, 1);
+    }
+
+    #[test]
+    fn get_in_reads_only_target() {
+        let config1 = ConfigJson::from_data(r#"{ "key": "value_1" }"#.to_string()).unwrap();
+        let config2 = ConfigJson::from_data(r#"{ "key": "value_2" }"#.to_string()).unwrap();
+
+        let mut provider = ConfigProvider::new();
+        provider.register_default_for(ConfigTarget::Machine, Config::Json(config1));
+        provider.register_default_for(ConfigTarget::Repo, Config::Json(config2));
+
+        let value = provider.get_in::<String>(ConfigTarget::Repo, "key");
+        assert_eq!(value, Some("value_2".to_string()));
+        assert_eq!(provider.get_in::<String>(ConfigTarget::Local, "key"), None);
+    }
+
+    #[test]
+    fn set_in_writes_to_target() {
+        let config1 = ConfigJson::from_data(r#"{ "key": "value_1" }"#.to_string()).unwrap();
+        let config2 = ConfigJson::from_data(r#"{ "key": "value_2" }"#.to_string()).unwrap();
+
+        let mut provider = ConfigProvider::new();
+        provider.register_default_for(ConfigTarget::Machine, Config::Json(config1));
+        provider.register_default_for(ConfigTarget::Repo, Config::Json(config2));
+
+        assert!(provider.set_in(ConfigTarget::Repo, "key", "value_3".to_string()));
+        assert_eq!(provider.get_in::<String>(ConfigTarget::Repo, "key"), Some("value_3".to_string()));
+        assert_eq!(provider.get::<String>("key"), Some("value_1".to_string()));
+    }
+
+    #[test]
+    fn unset_removes_key_from_all_layers() {
+        let mut provider = ConfigProvider::new();
+        provider.register_default(writable(r#"{ "key": "value_1" }"#));
+        provider.register_default(Config::None);
+        provider.register_default(writable(r#"{ "key": "value_2" }"#));
+
+        assert!(provider.unset("key"));
+        assert_eq!(provider.get::<String>("key"), None);
+        assert!(!provider.unset("key"));
+    }
+
+    #[test]
+    fn unset_in_removes_key_from_target_only() {
+        let mut provider = ConfigProvider::new();
+        provider.register_default_for(ConfigTarget::Machine, writable(r#"{ "key": "value_1" }"#));
+        provider.register_default_for(ConfigTarget::Repo, writable(r#"{ "key": "value_2" }"#));
+
+        assert!(provider.unset_in(ConfigTarget::Machine, "key"));
+        assert_eq!(provider.get::<String>("key"), Some("value_2".to_string()));
+    }
+
+    #[test]
+    fn unset_and_remove_skip_layers_that_cant_be_saved() {
+        let profile = ConfigJson::from_data(r#"{ "key": "profile", "list": ["a"] }"#.to_string()).unwrap();
+
+        let mut provider = ConfigProvider::new();
+        provider.register_default_for(ConfigTarget::Profile, Config::Json(profile));
+        provider.register_default_for(ConfigTarget::Machine, writable(r#"{ "key": "machine", "list": ["a"] }"#));
+
+        assert!(provider.unset("key"));
+        assert!(!provider.unset_in(ConfigTarget::Profile, "key"));
+        assert_eq!(provider.get::<String>("key"), Some("profile".to_string()));
+        assert_eq!(provider.get_origins("key").len(), 1);
+
+        assert!(provider.remove_value("list", &Value::from("a")));
+        assert!(!provider.remove_value_in(ConfigTarget::Profile, "list", &Value::from("a")));
+        assert_eq!(provider.get_value("list"), Some(serde_json::json!(["a"])));
+    }
+
+    #[test]
+    fn set_in_fails_without_json_target() {
+        let mut provider = ConfigProvider::new();
+        provider.register_default_for(ConfigTarget::Local, Config::None);
+
+        assert!(!provider.set_in(ConfigTarget::Local, "key", "value".to_string()));
+    }
+
+    #[test]
+    fn get_value_expands_references() {
+        let config = ConfigJson::from_data(r#"{
+            "base": "${env:SHT_INTERPOLATE_HOME}/scratch",
+            "path": "${config:base}/${repo.root}",
+            "count": 2,
+            "label": "run ${config:count}",
+            "list": ["${config:count}"]
+        }"#.to_string()).unwrap();
+
+        let mut provider = ConfigProvider::new();
+        provider.register_default(Config::Json(config));
+        provider.set_variable("repo.root", "repo".to_string());
+        std::env::set_var("SHT_INTERPOLATE_HOME", "/home/me");
+
+        assert_eq!(provider.get::<String>("path"), Some("/home/me/scratch/repo".to_string()));
+        assert_eq!(provider.get::<String>("label"), Some("run 2".to_string()));
+        assert_eq!(provider.get_value("list"), Some(serde_json::json!(["2"])));
+        std::env::remove_var("SHT_INTERPOLATE_HOME");
+    }
+
+    #[test]
+    fn resolve_value_reports_cycles_and_missing_references() {
+        let config = ConfigJson::from_data(r#"{
+            "a": "${config:b}",
+            "b": "x${config:a}",
+            "missing": "${config:nope}",
+            "var": "${nope}"
+        }"#.to_string()).unwrap();
+
+        let mut provider = ConfigProvider::new();
+        provider.register_default_for(ConfigTarget::Machine, Config::Json(config));
+
+        let err = provider.resolve_value("a").unwrap_err();
+        assert_eq!(err.message, "Cycle in config references: a -> b -> a");
+        assert!(provider.resolve_value_in(ConfigTarget::Machine, "b").is_err());
+        assert!(provider.resolve_value("missing").is_err());
+        assert!(provider.resolve_value("var").is_err());
+        assert_eq!(provider.get_value("a"), None);
+    }
+
+    #[test]
+    fn get_parses_string_values() {
+        let config = ConfigJson::from_data(r#"{ "count": "10" }"#.to_string()).unwrap();
+
+        let mut provider = ConfigProvider::new();
+        provider.register_default(Config::Json(config));
+
+        assert_eq!(provider.get::<i32>("count"), Some(10));
+        assert_eq!(provider.get::<String>("count"), Some("10".to_string()));
+    }
+
+    #[test]
+    fn get_merged_merges_objects_across_layers() {
+        let repo = ConfigJson::from_data(r#"{ "scripts": { "shell": "bash", "paths": ["repo"], "user": "${config:user}" } }"#.to_string()).unwrap();
+        let machine = ConfigJson::from_data(r#"{ "scripts": { "shell": "zsh", "paths": ["home"] }, "user": "me" }"#.to_string()).unwrap();
+
+        let mut provider = ConfigProvider::new();
+        provider.register_default(Config::Json(machine));
+        provider.register_default(Config::None);
+        provider.register_default(Config::Json(repo));
+
+        assert_eq!(
+            provider.get_merged("scripts"),
+            Some(serde_json::json!({ "shell": "zsh", "paths": ["home"], "user": "me" }))
+        );
+        assert_eq!(
+            provider.get_merged_with("scripts.paths", MergePolicy::Concat),
+            Some(serde_json::json!(["home", "repo"]))
+        );
+        assert_eq!(provider.get_merged("missing"), None);
+    }
+
+    #[test]
+    fn add_and_remove_value_edit_arrays() {
+        let mut provider = ConfigProvider::new();
+        provider.register_default_for(ConfigTarget::Machine, writable(r#"{ "list": ["a"] }"#));
+        provider.register_default_for(ConfigTarget::Repo, writable(r#"{ "list": ["a", "b"] }"#));
+
+        assert!(provider.add_value("list", Value::from("c")));
+        assert!(provider.add_value_in(ConfigTarget::Repo, "list", Value::from("d")));
+        assert_eq!(provider.get_value("list"), Some(serde_json::json!(["a", "c"])));
+        assert_eq!(provider.get_value_in(ConfigTarget::Repo, "list"), Some(serde_json::json!(["a", "b", "d"])));
+
+        assert!(provider.remove_value_in(ConfigTarget::Repo, "list", &Value::from("b")));
+        assert!(provider.remove_value("list", &Value::from("a")));
+        assert!(!provider.remove_value("list", &Value::from("a")));
+        assert_eq!(provider.get_value("list"), Some(serde_json::json!(["c"])));
+        assert_eq!(provider.get_value_in(ConfigTarget::Repo, "list"), Some(serde_json::json!(["d"])));
+    }
+
+    #[test]
+    fn entries_lists_effective_values() {
+        let machine = ConfigJson::from_data(r#"{ "core": { "git": { "user": "me" } }, "path": "${config:core.git.user}/x" }"#.to_string()).unwrap();
+        let repo = ConfigJson::from_data(r#"{ "core": { "git": { "user": "team", "email": "team@mail" } } }"#.to_string()).unwrap();
+
+        let mut provider = ConfigProvider::new();
+        provider.register_default_for(ConfigTarget::Machine, Config::Json(machine));
+        provider.register_default_for(ConfigTarget::Repo, Config::Json(repo));
+
+        let entries: Vec<(String, Value, Option<ConfigTarget>)> = provider.entries().into_iter()
+            .map(|(key, origin)| (key, origin.value, origin.target))
+            .collect();
+        assert_eq!(entries, vec![
+            ("core.git.email".to_string(), Value::from("team@mail"), Some(ConfigTarget::Repo)),
+            ("core.git.user".to_string(), Value::from("me"), Some(ConfigTarget::Machine)),
+            ("path".to_string(), Value::from("me/x"), Some(ConfigTarget::Machine)),
+        ]);
+    }
+
+    #[test]
+    fn get_prefix_and_matching_filter_entries() {
+        let config = ConfigJson::from_data(r#"{ "core": { "git": { "user": "me", "email": "me@mail" }, "gitx": 1, "log": { "level": "info" } } }"#.to_string()).unwrap();
+
+        let mut provider = ConfigProvider::new();
+        provider.register_default(Config::Json(config));
+
+        let keys = |entries: Vec<(String, ConfigOrigin)>| entries.into_iter().map(|(k, _)| k).collect::<Vec<String>>();
+        assert_eq!(keys(provider.get_prefix("core.git")), vec!["core.git.email", "core.git.user"]);
+        assert_eq!(keys(provider.get_prefix("core.git.user")), vec!["core.git.user"]);
+        assert_eq!(keys(provider.get_matching("core.git.*")), vec!["core.git.email", "core.git.user"]);
+        assert_eq!(keys(provider.get_matching("*.level")), vec!["core.log.level"]);
+        assert!(provider.get_prefix("other").is_empty());
+    }
+
+    #[test]
+    fn sync_records_changes_in_history() {
+        let dir = temp_dir("provider-history");
+        let file = dir.join("config.json");
+        std::fs::write(&file, r#"{ "schema_version": 1, "key": "old" }"#).unwrap();
+
+        let mut provider = ConfigProvider::new();
+        provider.register_default_for(ConfigTarget::Machine, Config::Json(ConfigJson::from_file(file.clone()).unwrap()));
+        provider.use_history(History::new(dir.join("history.jsonl")));
+
+        provider.set_value("key", Value::from("new"));
+        provider.set_value("other", Value::from(1));
+        assert!(provider.sync().iter().all(|r| r.is_ok()));
+
+        let entries = provider.history().unwrap().read().unwrap();
+        let changes: Vec<(String, Option<Value>, Option<Value>)> = entries.into_iter()
+            .map(|e| (e.key, e.old, e.new))
+            .collect();
+        assert_eq!(changes, vec![
+            ("key".to_string(), Some(Value::from("old")), Some(Value::from("new"))),
+            ("other".to_string(), None, Some(Value::from(1))),
+        ]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sync_only_writes_migrations_for_edited_layers() {
+        let dir = temp_dir("provider-migrate");
+        let machine = dir.join("machine.json");
+        let repo = dir.join("repo.json");
+        let content = "{\n  // team\n  \"core.git.user\": \"me\"\n}";
+        std::fs::write(&machine, content).unwrap();
+        std::fs::write(&repo, content).unwrap();
+
+        let mut provider = ConfigProvider::new();
+        provider.register_default_for(ConfigTarget::Machine, Config::Json(ConfigJson::from_file(machine.clone()).unwrap()));
+        provider.register_default_for(ConfigTarget::Repo, Config::Json(ConfigJson::from_file(repo.clone()).unwrap()));
+        provider.use_history(History::new(dir.join("history.jsonl")));
+
+        assert!(provider.set_value_in(ConfigTarget::Machine, "other", Value::from(1)));
+        assert!(provider.sync().iter().all(|r| r.is_ok()));
+        assert_eq!(std::fs::read_to_string(&repo).unwrap(), content);
+        assert!(std::fs::read_to_string(&machine).unwrap().contains(SCHEMA_VERSION_KEY));
+
+        let keys: Vec<String> = provider.history().unwrap().read().unwrap().into_iter()
+            .map(|e| e.key)
+            .collect();
+        assert!(!keys.iter().any(|k| k == SCHEMA_VERSION_KEY));
+
+        assert!(provider.stage_migrations());
+        assert!(provider.sync().iter().all(|r| r.is_ok()));
+        assert!(std::fs::read_to_string(&repo).unwrap().contains(SCHEMA_VERSION_KEY));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn secrets_are_encrypted_on_write_and_decrypted_on_read() {
+        let dir = temp_dir("provider-secret");
+        let config = ConfigJson::from_data(r#"{ "url": "https://host/?t=${config:token}" }"#.to_string()).unwrap();
+
+        let mut provider = ConfigProvider::new();
+        provider.register_default_for(ConfigTarget::Machine, Config::Json(config));
+        assert!(provider.set_secret("token", Value::from("abc")).is_err());
+
+        provider.use_secrets(Secrets::new(dir.join("secret.key")));
+        provider.set_secret("token", Value::from("abc")).unwrap();
+        let stored = provider.get_origins("token")[0].value.clone();
+        assert!(secret::is_secret(stored.as_str().unwrap()));
+        assert!(provider.is_secret("token"));
+        assert!(!provider.is_secret("url"));
+
+        assert_eq!(provider.get::<String>("token"), Some("abc".to_string()));
+        assert_eq!(provider.get::<String>("url"), Some("https://host/?t=abc".to_string()));
+
+        assert!(!provider.add_value("token", Value::from("plain")));
+        assert!(!provider.add_value_in(ConfigTarget::Machine, "token", Value::from("plain")));
+
+        provider.mask_secrets(true);
+        assert_eq!(provider.get::<String>("token"), Some(secret::MASK.to_string()));
+        assert_eq!(provider.get::<String>("url"), Some(format!("https://host/?t={}", secret::MASK)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn layer_values_merges_layers_of_target() {
+        let config1 = ConfigJson::from_data(r#"{ "a": "${env:HOME}", "core": { "script": { "paths": ["one"] } } }"#.to_string()).unwrap();
+        let config2 = ConfigJson::from_data(r#"{ "b": 1, "core": { "script": { "paths": ["two"] } } }"#.to_string()).unwrap();
+        let config3 = ConfigJson::from_data(r#"{ "c": true }"#.to_string()).unwrap();
+
+        let mut provider = ConfigProvider::new();
+        provider.register_default_for(ConfigTarget::Machine, Config::Json(config1));
+        provider.register_default_for(ConfigTarget::Machine, Config::Json(config2));
+        provider.register_default_for(ConfigTarget::Repo, Config::Json(config3));
+
+        assert_eq!(provider.layer_values(Some(ConfigTarget::Machine)), serde_json::json!({
+            "a": "${env:HOME}", "b": 1, "core": { "script": { "paths": ["one", "two"] } }
+        }));
+        assert_eq!(provider.layer_values(Some(ConfigTarget::Repo)), serde_json::json!({ "c": true }));
+        assert_eq!(provider.layer_values(Some(ConfigTarget::Local)), serde_json::json!({}));
+        assert_eq!(provider.layer_values(None)["c"], Value::Bool(true));
+    }
+
+    #[test]
+    fn import_merges_or_replaces_layer() {
+        let mut provider = ConfigProvider::new();
+        provider.register_default_for(ConfigTarget::Machine, writable(r#"{ "a": 1, "b": 2 }"#));
+
+        assert!(provider.import(None, &serde_json::json!({ "b": 3, "c": { "d": 4 } }), false).unwrap());
+        assert_eq!(provider.layer_values(None), serde_json::json!({ "a": 1, "b": 3, "c": { "d": 4 } }));
+
+        let token = format!("{}abc", secret::SECRET_PREFIX);
+        provider.set_value("token", Value::from(token.clone()));
+        assert!(provider.import(Some(ConfigTarget::Machine), &serde_json::json!({ "e": 5 }), true).unwrap());
+        assert_eq!(provider.layer_values(None), serde_json::json!({ "token": token, "e": 5 }));
+        assert!(!provider.import(Some(ConfigTarget::Repo), &serde_json::json!({ "e": 5 }), true).unwrap());
+
+        let profile = ConfigJson::from_data(r#"{ "f": 6 }"#.to_string()).unwrap();
+        provider.register_top_for(ConfigTarget::Profile, Config::Json(profile));
+        assert!(!provider.import(Some(ConfigTarget::Profile), &serde_json::json!({ "e": 5 }), false).unwrap());
+    }
+}
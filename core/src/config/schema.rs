@@ -0,0 +1,253 @@
+use serde_json::Value;
+
+use crate::config::KnownConfigs;
+use crate::config::merge::MergePolicy;
+use crate::enum_str;
+use crate::infra::error::CoreError;
+
+enum_str!(ValueKind {
+    Text = "string",
+    Integer = "integer",
+    Boolean = "boolean",
+    Array = "array",
+    Json = "json",
+});
+
+/// Describes a known config key: its type, default and accepted values.
+pub struct ConfigSchema {
+    pub key: KnownConfigs,
+    pub kind: ValueKind,
+    pub default: Option<&'static str>,
+    pub description: &'static str,
+    pub allowed: &'static [&'static str],
+    /// How arrays are combined when reading the key merged across layers.
+    pub merge: MergePolicy,
+}
+
+/// Converts a raw value for any key. Known keys are parsed and validated
+/// through their schema, other keys are kept as strings.
+pub fn parse_value(key: &str, raw: &str) -> Result<Value, CoreError> {
+    match KnownConfigs::from_str(key) {
+        Some(known) => known.schema().parse(raw),
+        None => Ok(Value::String(raw.to_string())),
+    }
+}
+
+impl KnownConfigs {
+    pub fn schema(&self) -> ConfigSchema {
+        match self {
+            KnownConfigs::RepoPath => ConfigSchema {
+                key: *self,
+                kind: ValueKind::Text,
+                default: None,
+                description: "Path to the scripts repository, relative to the machine config",
+                allowed: &[],
+                merge: MergePolicy::Replace,
+            },
+            KnownConfigs::GitUser => ConfigSchema {
+                key: *self,
+                kind: ValueKind::Text,
+                default: None,
+                description: "User name for commits made to the repository",
+                allowed: &[],
+                merge: MergePolicy::Replace,
+            },
+            KnownConfigs::GitEmail => ConfigSchema {
+                key: *self,
+                kind: ValueKind::Text,
+                default: None,
+                description: "Email for commits made to the repository",
+                allowed: &[],
+                merge: MergePolicy::Replace,
+            },
+            KnownConfigs::ScriptPaths => ConfigSchema {
+                key: *self,
+                kind: ValueKind::Array,
+                default: None,
+                description: "Extra folders searched for scripts, from every layer",
+                allowed: &[],
+                merge: MergePolicy::Concat,
+            },
+            KnownConfigs::Interpreters => ConfigSchema {
+                key: *self,
+                kind: ValueKind::Array,
+                default: None,
+                description: "Interpreters scripts are allowed to run with",
+                allowed: &[],
+                merge: MergePolicy::Replace,
+            },
+            KnownConfigs::Profile => ConfigSchema {
+                key: *self,
+                kind: ValueKind::Text,
+                default: None,
+                description: "Profile applied over the machine config, overridden by --profile and SH_PROFILE",
+                allowed: &[],
+                merge: MergePolicy::Replace,
+            },
+            KnownConfigs::LogLevel => ConfigSchema {
+                key: *self,
+                kind: ValueKind::Text,
+                default: Some("error"),
+                description: "Minimum level of log messages to print",
+                allowed: &["error", "warn", "info", "debug", "trace", "off"],
+                merge: MergePolicy::Replace,
+            },
+        }
+    }
+}
+
+/// Converts a raw value for any key to the given kind. Values of known
+/// keys are still checked against their schema.
+pub fn parse_value_as(key: &str, raw: &str, kind: ValueKind) -> Result<Value, CoreError> {
+    let value = match parse_as(kind, raw) {
+        Some(value) => value,
+        None => return Err(CoreError::for_app(format!("Invalid value for {}: {} (expected {})", key, raw, kind))),
+    };
+
+    match KnownConfigs::from_str(key) {
+        Some(known) => known.schema().normalize(value),
+        None => Ok(value),
+    }
+}
+
+/// Converts a raw value to the given kind, None if it does not match.
+pub fn parse_as(kind: ValueKind, raw: &str) -> Option<Value> {
+    match kind {
+        ValueKind::Text => Some(Value::String(raw.to_string())),
+        ValueKind::Integer => raw.trim().parse::<i64>().ok().map(Value::from),
+        ValueKind::Boolean => match raw.trim().to_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => Some(Value::Bool(true)),
+            "false" | "no" | "off" | "0" => Some(Value::Bool(false)),
+            _ => None,
+        },
+        ValueKind::Array => match serde_json::from_str::<Value>(raw) {
+            Ok(Value::Array(items)) => Some(Value::Array(items)),
+            _ => None,
+        },
+        ValueKind::Json => serde_json::from_str::<Value>(raw).ok(),
+    }
+}
+
+/// Merge policy for any key, `Replace` for keys without a schema.
+pub fn merge_policy(key: &str) -> MergePolicy {
+    match KnownConfigs::from_str(key) {
+        Some(known) => known.schema().merge,
+        None => MergePolicy::Replace,
+    }
+}
+
+impl ConfigSchema {
+    pub fn default_value(&self) -> Option<Value> {
+        self.default.and_then(|d| self.parse(d).ok())
+    }
+
+    /// Converts a raw command line value into a typed value for this key.
+    pub fn parse(&self, raw: &str) -> Result<Value, CoreError> {
+        match parse_as(self.kind, raw) {
+            Some(value) => self.normalize(value),
+            None => Err(self.invalid(raw)),
+        }
+    }
+
+    /// Checks a value against the key type and allowed values.
+    pub fn validate(&self, value: &Value) -> Result<(), CoreError> {
+        self.normalize(value.clone()).map(|_| ())
+    }
+
+    fn normalize(&self, value: Value) -> Result<Value, CoreError> {
+        let matches_kind = match self.kind {
+            ValueKind::Text => value.is_string(),
+            ValueKind::Integer => value.is_i64(),
+            ValueKind::Boolean => value.is_boolean(),
+            ValueKind::Array => value.is_array(),
+            ValueKind::Json => true,
+        };
+
+        if !matches_kind {
+            return Err(self.invalid(&value.to_string()));
+        }
+
+        if self.allowed.is_empty() {
+            return Ok(value);
+        }
+
+        match value.as_str().map(|s| s.to_lowercase()) {
+            Some(s) if self.allowed.contains(&s.as_str()) => Ok(Value::String(s)),
+            _ => Err(self.invalid(&value.to_string())),
+        }
+    }
+
+    fn invalid(&self, raw: &str) -> CoreError {
+        let expected = match self.allowed.is_empty() {
+            true => format!("expected {}", self.kind),
+            false => format!("expected one of: {}", self.allowed.join(", ")),
+        };
+
+        CoreError::for_app(format!("Invalid value for {}: {} ({})", self.key, raw, expected))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_value_is_parsed() {
+        assert_eq!(KnownConfigs::LogLevel.schema().default_value(), Some(Value::String("error".to_string())));
+        assert_eq!(KnownConfigs::RepoPath.schema().default_value(), None);
+    }
+
+    #[test]
+    fn parse_accepts_allowed_value() {
+        let value = KnownConfigs::LogLevel.schema().parse("WARN").unwrap();
+        assert_eq!(value, Value::String("warn".to_string()));
+    }
+
+    #[test]
+    fn parse_rejects_unknown_value() {
+        assert!(KnownConfigs::LogLevel.schema().parse("wanr").is_err());
+    }
+
+    #[test]
+    fn parse_converts_to_kind() {
+        let mut schema = KnownConfigs::RepoPath.schema();
+        schema.kind = ValueKind::Integer;
+        assert_eq!(schema.parse("10").unwrap(), Value::from(10));
+        assert!(schema.parse("ten").is_err());
+
+        schema.kind = ValueKind::Boolean;
+        assert_eq!(schema.parse("yes").unwrap(), Value::Bool(true));
+
+        schema.kind = ValueKind::Array;
+        assert_eq!(schema.parse("[1]").unwrap(), serde_json::json!([1]));
+        assert!(schema.parse("1").is_err());
+    }
+
+    #[test]
+    fn parse_value_keeps_unknown_keys_as_text() {
+        assert_eq!(parse_value("my.key", "10").unwrap(), Value::String("10".to_string()));
+        assert!(parse_value("core.log.level", "wanr").is_err());
+    }
+
+    #[test]
+    fn parse_value_as_coerces_to_kind() {
+        assert_eq!(parse_value_as("my.key", "10", ValueKind::Integer).unwrap(), Value::from(10));
+        assert_eq!(parse_value_as("my.key", "off", ValueKind::Boolean).unwrap(), Value::Bool(false));
+        assert_eq!(parse_value_as("my.key", r#"{"a":[1]}"#, ValueKind::Json).unwrap(), serde_json::json!({ "a": [1] }));
+        assert!(parse_value_as("my.key", "ten", ValueKind::Integer).is_err());
+        assert!(parse_value_as("my.key", "{", ValueKind::Json).is_err());
+    }
+
+    #[test]
+    fn parse_value_as_checks_known_keys() {
+        assert!(parse_value_as("core.log.level", "1", ValueKind::Integer).is_err());
+        assert_eq!(parse_value_as("core.log.level", r#""INFO""#, ValueKind::Json).unwrap(), Value::from("info"));
+    }
+
+    #[test]
+    fn validate_checks_kind() {
+        let schema = KnownConfigs::GitUser.schema();
+        assert!(schema.validate(&Value::String("me".to_string())).is_ok());
+        assert!(schema.validate(&Value::from(1)).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde_json::Value;
+
+use crate::infra::error::CoreError;
+
+/// Start of an encrypted value, followed by the base64 of the nonce and
+/// the ciphertext.
+pub const SECRET_PREFIX: &str = "secret:v1:";
+/// Printed in place of secret values.
+pub const MASK: &str = "********";
+
+const NONCE_LEN: usize = 12;
+
+/// Encrypts and decrypts secret values with a key file kept on this
+/// machine. The key is created the first time a secret is written.
+pub struct Secrets {
+    path: PathBuf,
+}
+
+impl Secrets {
+    pub fn new(path: PathBuf) -> Secrets {
+        Secrets { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Encrypts the value, as JSON, into a secret string.
+    pub fn encrypt(&self, value: &Value) -> Result<Value, CoreError> {
+        let cipher = ChaCha20Poly1305::new(&self.load_or_create_key()?);
+        let plain = serde_json::to_vec(value).map_err(|e| CoreError::for_err(Box::new(e)))?;
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut data = nonce.to_vec();
+        data.extend(cipher.encrypt(&nonce, plain.as_slice())
+            .map_err(|_| CoreError::for_app("Cannot encrypt secret".to_string()))?);
+
+        Ok(Value::String(format!("{}{}", SECRET_PREFIX, STANDARD.encode(data))))
+    }
+
+    /// Decrypts a secret string back into the value it was written from.
+    pub fn decrypt(&self, text: &str) -> Result<Value, CoreError> {
+        let encoded = text.strip_prefix(SECRET_PREFIX)
+            .ok_or_else(|| CoreError::for_app("Value is not a secret".to_string()))?;
+        let data = STANDARD.decode(encoded)
+            .map_err(|e| CoreError::for_app(format!("Invalid secret: {}", e)))?;
+        if data.len() < NONCE_LEN {
+            return Err(CoreError::for_app("Invalid secret: too short".to_string()));
+        }
+
+        let key = match self.load_key()? {
+            Some(key) => key,
+            None => return Err(CoreError::for_app(format!("Cannot decrypt secret, no key at {}", self.path.display()))),
+        };
+
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let plain = ChaCha20Poly1305::new(&key)
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| CoreError::for_app(format!("Cannot decrypt secret with the key at {}", self.path.display())))?;
+        serde_json::from_slice(&plain).map_err(|e| CoreError::for_err(Box::new(e)))
+    }
+
+    fn load_key(&self) -> Result<Option<Key>, CoreError> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        match STANDARD.decode(content.trim()) {
+            Ok(bytes) if bytes.len() == 32 => Ok(Some(*Key::from_slice(&bytes))),
+            _ => Err(CoreError::for_app(format!("Invalid secret key file: {}", self.path.display()))),
+        }
+    }
+
+    fn load_or_create_key(&self) -> Result<Key, CoreError> {
+        if let Some(key) = self.load_key()? {
+            return Ok(key);
+        }
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let key = ChaCha20Poly1305::generate_key(&mut OsRng);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut file = options.open(&self.path)?;
+        file.write_all(STANDARD.encode(key).as_bytes())?;
+        file.sync_all()?;
+        Ok(key)
+    }
+}
+
+/// Whether the text is an encrypted value.
+pub fn is_secret(text: &str) -> bool {
+    text.starts_with(SECRET_PREFIX)
+}
+
+/// Whether the value is, or holds, an encrypted value.
+pub fn contains_secret(value: &Value) -> bool {
+    match value {
+        Value::String(text) => is_secret(text),
+        Value::Array(items) => items.iter().any(contains_secret),
+        Value::Object(map) => map.values().any(contains_secret),
+        _ => false,
+    }
+}
+
+/// Replaces every encrypted value with `MASK`.
+pub fn mask(value: Value) -> Value {
+    match value {
+        Value::String(text) if is_secret(&text) => Value::String(MASK.to_string()),
+        Value::Array(items) => Value::Array(items.into_iter().map(mask).collect()),
+        Value::Object(map) => Value::Object(map.into_iter().map(|(k, v)| (k, mask(v))).collect()),
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::infra::testing::temp_dir;
+
+    #[test]
+    fn encrypt_round_trips_and_creates_key() {
+        let dir = temp_dir("secret-round");
+        let secrets = Secrets::new(dir.join("config").join("secret.key"));
+
+        let value = json!({ "token": "abc", "port": 8080 });
+        let encrypted = secrets.encrypt(&value).unwrap();
+        let text = encrypted.as_str().unwrap();
+        assert!(is_secret(text));
+        assert!(!text.contains("abc"));
+        assert!(secrets.path().exists());
+        assert_eq!(secrets.decrypt(text).unwrap(), value);
+
+        let again = secrets.encrypt(&value).unwrap();
+        assert_ne!(again, encrypted);
+        assert_eq!(secrets.decrypt(again.as_str().unwrap()).unwrap(), value);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn decrypt_fails_without_matching_key() {
+        let dir = temp_dir("secret-key");
+        let encrypted = Secrets::new(dir.join("a.key")).encrypt(&json!("abc")).unwrap();
+        let text = encrypted.as_str().unwrap();
+
+        assert!(Secrets::new(dir.join("missing.key")).decrypt(text).is_err());
+        let other = Secrets::new(dir.join("b.key"));
+        other.encrypt(&json!(1)).unwrap();
+        assert!(other.decrypt(text).is_err());
+        assert!(other.decrypt("plain").is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn mask_hides_nested_secrets() {
+        let value = json!({ "user": "me", "token": "secret:v1:AAAA", "list": ["secret:v1:BBBB", 1] });
+        assert!(contains_secret(&value));
+        assert!(!contains_secret(&json!({ "user": "me" })));
+        assert_eq!(mask(value), json!({ "user": "me", "token": MASK, "list": [MASK, 1] }));
+    }
+}
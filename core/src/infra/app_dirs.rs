@@ -0,0 +1,192 @@
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::infra::error::CoreError;
+
+pub const APP_NAME: &str = "script-herder";
+const LEGACY_CONFIG: &str = ".config-sh";
+
+/// Locations used by the tool, following the XDG base directory spec.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppDirs {
+    pub home: PathBuf,
+    /// User settings, `$XDG_CONFIG_HOME/script-herder`.
+    pub config: PathBuf,
+    /// Data that should persist between runs but is not worth syncing,
+    /// `$XDG_STATE_HOME/script-herder`.
+    pub state: PathBuf,
+    /// Data that can be rebuilt at any time, `$XDG_CACHE_HOME/script-herder`.
+    pub cache: PathBuf,
+}
+
+impl AppDirs {
+    /// Resolves the directories for the current user, None if there is no
+    /// home directory.
+    pub fn from_env() -> Option<AppDirs> {
+        dirs::home_dir().map(|home| AppDirs::from_vars(home, |var| std::env::var_os(var)))
+    }
+
+    fn from_vars(home: PathBuf, var: impl Fn(&str) -> Option<OsString>) -> AppDirs {
+        let base = |name: &str, default: &[&str]| {
+            match var(name).map(PathBuf::from) {
+                Some(path) if path.is_absolute() => path,
+                _ => default.iter().fold(home.clone(), |p, part| p.join(part)),
+            }
+        };
+
+        AppDirs {
+            config: base("XDG_CONFIG_HOME", &[".config"]).join(APP_NAME),
+            state: base("XDG_STATE_HOME", &[".local", "state"]).join(APP_NAME),
+            cache: base("XDG_CACHE_HOME", &[".cache"]).join(APP_NAME),
+            home,
+        }
+    }
+
+    /// Default machine config file.
+    pub fn config_file(&self) -> PathBuf {
+        self.config.join("config.json")
+    }
+
+    /// Key used to encrypt secret config values, see `config::secret`.
+    pub fn secret_key_file(&self) -> PathBuf {
+        self.config.join("secret.key")
+    }
+
+    /// Journal of config changes, see `config::history`.
+    pub fn history_file(&self) -> PathBuf {
+        self.state.join("history.jsonl")
+    }
+
+    /// Lock file guarding saves of a config file, see `io::ConfigFile`.
+    /// Kept under the state dir so config folders and repositories don't
+    /// collect lock files; the name is derived from the canonical path.
+    pub fn lock_file(&self, path: &Path) -> PathBuf {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        self.state.join("locks").join(format!("{}-{:016x}.lock", name, fnv1a(path.to_string_lossy().as_bytes())))
+    }
+
+    /// Machine config file used before the XDG layout.
+    pub fn legacy_config_file(&self) -> PathBuf {
+        self.home.join(format!("{}.json", LEGACY_CONFIG))
+    }
+
+    /// Moves the legacy machine config (JSON or TOML) to the XDG location,
+    /// unless a config already exists there. `prepare` gets the legacy file
+    /// before it is moved, to rewrite what depends on its location; nothing
+    /// is moved if it fails. Returns the moved file paths.
+    pub fn migrate_legacy_config<F>(&self, prepare: F) -> Result<Option<(PathBuf, PathBuf)>, CoreError>
+    where F: FnOnce(&Path) -> Result<(), CoreError> {
+        let target = self.config_file();
+        if target.exists() || target.with_extension("toml").exists() {
+            return Ok(None);
+        }
+
+        let legacy = self.legacy_config_file();
+        for ext in ["json", "toml"] {
+            let from = legacy.with_extension(ext);
+            if from.exists() {
+                let to = target.with_extension(ext);
+                prepare(&from)?;
+                move_file(&from, &to)?;
+                return Ok(Some((from, to)));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+fn move_file(from: &Path, to: &Path) -> Result<(), CoreError> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+
+    Ok(())
+}
+
+/// 64 bit FNV-1a, stable across builds unlike the std hasher.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::testing::temp_dir;
+
+    #[test]
+    fn from_vars_uses_defaults() {
+        let dirs = AppDirs::from_vars(PathBuf::from("/home/me"), |_| None);
+        assert_eq!(dirs.config, PathBuf::from("/home/me/.config/script-herder"));
+        assert_eq!(dirs.state, PathBuf::from("/home/me/.local/state/script-herder"));
+        assert_eq!(dirs.cache, PathBuf::from("/home/me/.cache/script-herder"));
+        assert_eq!(dirs.config_file(), PathBuf::from("/home/me/.config/script-herder/config.json"));
+    }
+
+    #[test]
+    fn from_vars_uses_xdg_vars() {
+        let dirs = AppDirs::from_vars(PathBuf::from("/home/me"), |var| match var {
+            "XDG_CONFIG_HOME" => Some(OsString::from("/xdg/config")),
+            "XDG_CACHE_HOME" => Some(OsString::from("relative")),
+            _ => None,
+        });
+        assert_eq!(dirs.config, PathBuf::from("/xdg/config/script-herder"));
+        assert_eq!(dirs.cache, PathBuf::from("/home/me/.cache/script-herder"));
+    }
+
+    #[test]
+    fn lock_file_is_keyed_by_path() {
+        let dirs = AppDirs::from_vars(PathBuf::from("/home/me"), |_| None);
+        let lock = dirs.lock_file(Path::new("/repo/.config-sh.json"));
+        assert_eq!(lock.parent(), Some(dirs.state.join("locks").as_path()));
+        assert!(lock.file_name().unwrap().to_string_lossy().starts_with(".config-sh.json-"));
+        assert_eq!(lock, dirs.lock_file(Path::new("/repo/.config-sh.json")));
+        assert_ne!(lock, dirs.lock_file(Path::new("/other/.config-sh.json")));
+    }
+
+    #[test]
+    fn migrate_moves_legacy_config() {
+        let home = temp_dir("dirs-migrate");
+        let dirs = AppDirs::from_vars(home.clone(), |_| None);
+        fs::write(dirs.legacy_config_file(), "{}").unwrap();
+
+        let moved = dirs.migrate_legacy_config(|_| Ok(())).unwrap();
+        assert_eq!(moved, Some((dirs.legacy_config_file(), dirs.config_file())));
+        assert!(dirs.config_file().exists());
+        assert!(!dirs.legacy_config_file().exists());
+
+        assert_eq!(dirs.migrate_legacy_config(|_| Ok(())).unwrap(), None);
+        fs::remove_dir_all(home).unwrap();
+    }
+
+    #[test]
+    fn migrate_keeps_existing_config() {
+        let home = temp_dir("dirs-existing");
+        let dirs = AppDirs::from_vars(home.clone(), |_| None);
+        fs::write(dirs.legacy_config_file(), "{}").unwrap();
+        fs::create_dir_all(&dirs.config).unwrap();
+        fs::write(dirs.config_file(), "{}").unwrap();
+
+        assert_eq!(dirs.migrate_legacy_config(|_| Ok(())).unwrap(), None);
+        assert!(dirs.legacy_config_file().exists());
+        fs::remove_dir_all(home).unwrap();
+    }
+
+    #[test]
+    fn migrate_keeps_legacy_config_if_prepare_fails() {
+        let home = temp_dir("dirs-prepare");
+        let dirs = AppDirs::from_vars(home.clone(), |_| None);
+        fs::write(dirs.legacy_config_file(), "{}").unwrap();
+
+        assert!(dirs.migrate_legacy_config(|_| Err(CoreError::for_app("no".to_string()))).is_err());
+        assert!(dirs.legacy_config_file().exists());
+        assert!(!dirs.config_file().exists());
+        fs::remove_dir_all(home).unwrap();
+    }
+}
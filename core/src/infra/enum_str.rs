@@ -13,6 +13,7 @@ macro_rules! enum_str {
                 }
             }
 
+            #[allow(clippy::should_implement_trait)]
             pub fn from_str(value: &str) -> Option<$name> {
                 match value {
                     $($value => Some($name::$variant),)*
@@ -0,0 +1,33 @@
+/// Facts about the machine used to pick conditional config.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostInfo {
+    /// Operating system, as in `std::env::consts::OS` (`linux`, `macos`, `windows`).
+    pub os: String,
+    /// CPU architecture, as in `std::env::consts::ARCH` (`x86_64`, `aarch64`).
+    pub arch: String,
+    /// Host name, lowercase.
+    pub host: String,
+}
+
+impl HostInfo {
+    pub fn current() -> HostInfo {
+        HostInfo {
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            host: gethostname::gethostname().to_string_lossy().to_lowercase(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn current_reads_build_target() {
+        let host = HostInfo::current();
+        assert_eq!(host.os, std::env::consts::OS);
+        assert_eq!(host.arch, std::env::consts::ARCH);
+        assert_eq!(host.host, host.host.to_lowercase());
+    }
+}
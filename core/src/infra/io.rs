@@ -1,6 +1,12 @@
 use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
 use std::path::PathBuf;
-use std::fs;
+
+use fs2::FileExt;
+
+use crate::infra::app_dirs::{AppDirs, APP_NAME};
 
 pub struct ConfigFile {
     pub path: PathBuf,
@@ -13,12 +19,78 @@ pub struct ConfigData {
 pub trait ConfigIO {
     fn read(&self) -> Result<String, Box<dyn Error>>;
     fn write(&mut self, data: &str) -> Result<(), Box<dyn Error>>;
+
+    /// Location of the underlying file, if the source is backed by one.
+    fn path(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// Writes the data only if the source still holds `expected`, the
+    /// content read when it was loaded. Fails with `ConflictError` when it
+    /// was changed in between.
+    ///
+    /// Nothing is held between load and save: this check is what keeps
+    /// concurrent commands from losing each other's changes, the loser
+    /// gets the error and runs again instead of waiting on a lock.
+    fn write_if_unchanged(&mut self, expected: Option<&str>, data: &str) -> Result<(), Box<dyn Error>> {
+        check_unchanged(self.read().ok().as_deref(), expected, self.path())?;
+        self.write(data)
+    }
+}
+
+/// The source changed since it was loaded, saving would overwrite
+/// someone else's changes.
+#[derive(Debug)]
+pub struct ConflictError {
+    pub path: Option<PathBuf>,
+}
+
+impl fmt::Display for ConflictError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "Config file {} changed since it was loaded, run the command again", path.display()),
+            None => write!(f, "Config changed since it was loaded, run the command again"),
+        }
+    }
+}
+
+impl Error for ConflictError {}
+
+fn check_unchanged(current: Option<&str>, expected: Option<&str>, path: Option<PathBuf>) -> Result<(), ConflictError> {
+    match (current, expected) {
+        (Some(current), Some(expected)) if current != expected => Err(ConflictError { path }),
+        _ => Ok(()),
+    }
 }
 
 impl ConfigFile {
     pub fn new(path: PathBuf) -> ConfigFile {
         ConfigFile { path }
     }
+
+    /// Takes the advisory lock shared by every process saving this file,
+    /// held until the returned handle is dropped, so the conflict check
+    /// and the write happen as one step. The config itself is replaced on
+    /// every write, so the lock lives in a separate file, see
+    /// `AppDirs::lock_file`.
+    fn lock(&self) -> Result<File, Box<dyn Error>> {
+        let target = fs::canonicalize(&self.path).unwrap_or_else(|_| self.path.clone());
+        let path = match AppDirs::from_env() {
+            Some(dirs) => dirs.lock_file(&target),
+            None => std::env::temp_dir().join(APP_NAME).join("locks").join(target.file_name().unwrap_or_default()),
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        lock.lock_exclusive()?;
+        Ok(lock)
+    }
 }
 
 impl ConfigIO for ConfigFile {
@@ -27,9 +99,39 @@ impl ConfigIO for ConfigFile {
         Ok(content)
     }
 
+    /// Writes to a temporary file next to the config and renames it over
+    /// the config, so a crash never leaves a truncated file behind.
     fn write(&mut self, data: &str) -> Result<(), Box<dyn Error>> {
-        fs::write(&self.path, data)?;
-        Ok(())
+        let target = fs::canonicalize(&self.path).unwrap_or_else(|_| self.path.clone());
+        let mut name = target.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".{}.tmp", std::process::id()));
+        let temp = target.with_file_name(name);
+
+        let result = (|| -> Result<(), Box<dyn Error>> {
+            let mut file = File::create(&temp)?;
+            file.write_all(data.as_bytes())?;
+            file.sync_all()?;
+            if let Ok(meta) = fs::metadata(&target) {
+                fs::set_permissions(&temp, meta.permissions())?;
+            }
+            fs::rename(&temp, &target)?;
+            Ok(())
+        })();
+
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        result
+    }
+
+    fn path(&self) -> Option<PathBuf> {
+        Some(self.path.clone())
+    }
+
+    fn write_if_unchanged(&mut self, expected: Option<&str>, data: &str) -> Result<(), Box<dyn Error>> {
+        let _lock = self.lock()?;
+        check_unchanged(fs::read_to_string(&self.path).ok().as_deref(), expected, self.path())?;
+        self.write(data)
     }
 }
 
@@ -53,6 +155,7 @@ impl ConfigIO for ConfigData {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::infra::testing::temp_dir;
 
     #[test]
     fn config_data_has_init_data() {
@@ -68,6 +171,18 @@ mod tests {
         assert_eq!(result.unwrap(), "test");
     }
 
+    #[test]
+    fn config_data_has_no_path() {
+        let data = ConfigData::new("test".to_string());
+        assert_eq!(data.path(), None);
+    }
+
+    #[test]
+    fn config_file_returns_path() {
+        let file = ConfigFile::new(PathBuf::from("config.json"));
+        assert_eq!(file.path(), Some(PathBuf::from("config.json")));
+    }
+
     #[test]
     fn config_data_can_write() {
         let mut data = ConfigData::new("test".to_string());
@@ -75,4 +190,41 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!(data.data, "test2");
     }
+
+    #[test]
+    fn config_file_write_replaces_content() {
+        let dir = temp_dir("io-write");
+        let path = dir.join("config.json");
+        fs::write(&path, "old content").unwrap();
+
+        let mut file = ConfigFile::new(path.clone());
+        file.write("new").unwrap();
+        assert_eq!(file.read().unwrap(), "new");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn config_file_write_if_unchanged_detects_conflict() {
+        let dir = temp_dir("io-conflict");
+        let path = dir.join("config.json");
+        fs::write(&path, "loaded").unwrap();
+
+        let mut file = ConfigFile::new(path.clone());
+        file.write_if_unchanged(Some("loaded"), "first").unwrap();
+        let err = file.write_if_unchanged(Some("loaded"), "second").unwrap_err();
+        assert!(err.downcast_ref::<ConflictError>().is_some());
+        assert_eq!(file.read().unwrap(), "first");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn config_data_write_if_unchanged_detects_conflict() {
+        let mut data = ConfigData::new("loaded".to_string());
+        assert!(data.write_if_unchanged(Some("other"), "new").is_err());
+        assert!(data.write_if_unchanged(Some("loaded"), "new").is_ok());
+        assert!(data.write_if_unchanged(None, "newer").is_ok());
+        assert_eq!(data.data, "newer");
+    }
 }
@@ -0,0 +1,532 @@
+use serde_json::{Map, Value};
+
+/// Blanks out `//` and `/* */` comments and trailing commas so the text can
+/// be parsed as plain JSON.
+///
+/// Every removed byte is replaced by a space (newlines are kept), so byte
+/// offsets in the result match the original text.
+pub fn strip_comments(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut in_string = false;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let b = bytes[i];
+        if in_string {
+            out.push(b);
+            if b == b'\\' && i + 1 < bytes.len() {
+                out.push(bytes[i + 1]);
+                i += 2;
+                continue;
+            }
+            in_string = b != b'"';
+            i += 1;
+        } else if b == b'"' {
+            in_string = true;
+            out.push(b);
+            i += 1;
+        } else if b == b'/' && bytes.get(i + 1) == Some(&b'/') {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                out.push(b' ');
+                i += 1;
+            }
+        } else if b == b'/' && bytes.get(i + 1) == Some(&b'*') {
+            out.extend_from_slice(b"  ");
+            i += 2;
+            while i < bytes.len() && !(bytes[i] == b'*' && bytes.get(i + 1) == Some(&b'/')) {
+                out.push(if bytes[i] == b'\n' { b'\n' } else { b' ' });
+                i += 1;
+            }
+            if i < bytes.len() {
+                out.extend_from_slice(b"  ");
+                i += 2;
+            }
+        } else {
+            out.push(b);
+            i += 1;
+        }
+    }
+
+    blank_trailing_commas(&mut out);
+    String::from_utf8(out).unwrap_or_default()
+}
+
+/// Rewrites `text` so it holds `data`, touching only the entries that
+/// changed. Key order, formatting and comments elsewhere are kept.
+///
+/// Returns None if the text is not a JSON object or the edit can't be
+/// done safely, so the caller can fall back to a full rewrite.
+pub fn update(text: &str, data: &Value) -> Option<String> {
+    let stripped = strip_comments(text);
+    let old: Value = serde_json::from_str(&stripped).ok()?;
+    let (old, new) = match (&old, data) {
+        (Value::Object(old), Value::Object(new)) => (old, new),
+        _ => return None,
+    };
+
+    let parser = Parser { bytes: stripped.as_bytes() };
+    let root = parser.object(parser.skip_ws(0))?;
+
+    let mut editor = Editor {
+        bytes: stripped.as_bytes(),
+        unit: detect_indent(stripped.as_bytes(), &root),
+        edits: Vec::new(),
+    };
+    editor.update_object(&root, old, new, "");
+
+    let mut result = text.to_string();
+    editor.edits.sort_by(|a, b| b.start.cmp(&a.start).then(b.end.cmp(&a.end)));
+    for edit in editor.edits {
+        result.replace_range(edit.start..edit.end, &edit.text);
+    }
+
+    match serde_json::from_str::<Value>(&strip_comments(&result)) {
+        Ok(check) if &check == data => Some(result),
+        _ => None,
+    }
+}
+
+fn blank_trailing_commas(bytes: &mut [u8]) {
+    let mut in_string = false;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if in_string => i += 1,
+            b'"' => in_string = !in_string,
+            b',' if !in_string => {
+                let next = bytes[i + 1..].iter().find(|b| !b.is_ascii_whitespace());
+                if matches!(next, Some(b'}') | Some(b']')) {
+                    bytes[i] = b' ';
+                }
+            },
+            _ => (),
+        }
+        i += 1;
+    }
+}
+
+fn detect_indent(bytes: &[u8], root: &ObjectNode) -> String {
+    let indent = root.members.first()
+        .and_then(|m| line_indent(bytes, m.start))
+        .unwrap_or_default();
+
+    if indent.is_empty() {
+        "  ".to_string()
+    } else {
+        indent
+    }
+}
+
+fn line_start(bytes: &[u8], pos: usize) -> usize {
+    match bytes[..pos].iter().rposition(|b| *b == b'\n') {
+        Some(nl) => nl + 1,
+        None => 0,
+    }
+}
+
+/// Leading whitespace of the line at `pos`, if nothing else precedes `pos`.
+fn line_indent(bytes: &[u8], pos: usize) -> Option<String> {
+    let start = line_start(bytes, pos);
+    let prefix = &bytes[start..pos];
+    if prefix.iter().all(|b| *b == b' ' || *b == b'\t') {
+        String::from_utf8(prefix.to_vec()).ok()
+    } else {
+        None
+    }
+}
+
+struct ObjectNode {
+    open: usize,
+    close: usize,
+    members: Vec<Member>,
+}
+
+struct Member {
+    key: String,
+    start: usize,
+    value_start: usize,
+    value_end: usize,
+    object: Option<ObjectNode>,
+    comma: Option<usize>,
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Parser<'a> {
+    fn skip_ws(&self, mut pos: usize) -> usize {
+        while pos < self.bytes.len() && self.bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        pos
+    }
+
+    fn object(&self, open: usize) -> Option<ObjectNode> {
+        if self.bytes.get(open) != Some(&b'{') {
+            return None;
+        }
+
+        let mut members = Vec::new();
+        let mut pos = self.skip_ws(open + 1);
+        loop {
+            match self.bytes.get(pos)? {
+                b'}' => return Some(ObjectNode { open, close: pos, members }),
+                b'"' => {
+                    let key_end = self.string_end(pos)?;
+                    let key: String = serde_json::from_slice(&self.bytes[pos..key_end]).ok()?;
+                    let colon = self.skip_ws(key_end);
+                    if self.bytes.get(colon) != Some(&b':') {
+                        return None;
+                    }
+
+                    let value_start = self.skip_ws(colon + 1);
+                    let (value_end, object) = self.value(value_start)?;
+                    let after = self.skip_ws(value_end);
+                    let comma = match self.bytes.get(after) {
+                        Some(b',') => Some(after),
+                        _ => None,
+                    };
+
+                    members.push(Member { key, start: pos, value_start, value_end, object, comma });
+                    pos = self.skip_ws(comma.map(|c| c + 1).unwrap_or(after));
+                },
+                _ => return None,
+            }
+        }
+    }
+
+    fn value(&self, start: usize) -> Option<(usize, Option<ObjectNode>)> {
+        match self.bytes.get(start)? {
+            b'{' => {
+                let node = self.object(start)?;
+                Some((node.close + 1, Some(node)))
+            },
+            b'[' => Some((self.array_end(start)?, None)),
+            b'"' => Some((self.string_end(start)?, None)),
+            _ => {
+                let mut pos = start;
+                while pos < self.bytes.len()
+                    && !matches!(self.bytes[pos], b',' | b'}' | b']')
+                    && !self.bytes[pos].is_ascii_whitespace() {
+                    pos += 1;
+                }
+                Some((pos, None))
+            },
+        }
+    }
+
+    fn string_end(&self, start: usize) -> Option<usize> {
+        let mut pos = start + 1;
+        while pos < self.bytes.len() {
+            match self.bytes[pos] {
+                b'\\' => pos += 2,
+                b'"' => return Some(pos + 1),
+                _ => pos += 1,
+            }
+        }
+        None
+    }
+
+    fn array_end(&self, start: usize) -> Option<usize> {
+        let mut depth = 0;
+        let mut pos = start;
+        while pos < self.bytes.len() {
+            match self.bytes[pos] {
+                b'"' => {
+                    pos = self.string_end(pos)?;
+                    continue;
+                },
+                b'[' | b'{' => depth += 1,
+                b']' | b'}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(pos + 1);
+                    }
+                },
+                _ => (),
+            }
+            pos += 1;
+        }
+        None
+    }
+}
+
+struct Edit {
+    start: usize,
+    end: usize,
+    text: String,
+}
+
+struct Editor<'a> {
+    bytes: &'a [u8],
+    unit: String,
+    edits: Vec<Edit>,
+}
+
+impl<'a> Editor<'a> {
+    fn update_object(&mut self, node: &ObjectNode, old: &Map<String, Value>, new: &Map<String, Value>, outer: &str) {
+        if !self.bytes[node.open..node.close].contains(&b'\n') && !node.members.is_empty() {
+            return self.update_inline_object(node, old, new);
+        }
+
+        let inner = node.members.first()
+            .and_then(|m| line_indent(self.bytes, m.start))
+            .unwrap_or_else(|| format!("{}{}", outer, self.unit));
+
+        let added: Vec<(&String, &Value)> = new.iter()
+            .filter(|(key, _)| !node.members.iter().any(|m| &m.key == *key))
+            .collect();
+        let kept: Vec<&Member> = node.members.iter()
+            .filter(|m| new.contains_key(&m.key))
+            .collect();
+
+        let last = match kept.last() {
+            Some(last) => *last,
+            None => {
+                let text = match added.is_empty() {
+                    true => String::new(),
+                    false => format!("\n{}\n{}", self.render_members(&added, &inner, ",\n"), outer),
+                };
+                self.edits.push(Edit { start: node.open + 1, end: node.close, text });
+                return;
+            }
+        };
+
+        for member in node.members.iter().filter(|m| !new.contains_key(&m.key)) {
+            self.remove_member(member);
+        }
+
+        for member in &kept {
+            let value = &new[&member.key];
+            match (&member.object, old.get(&member.key), value) {
+                (_, Some(old_value), _) if old_value == value => (),
+                (Some(child), Some(Value::Object(old_map)), Value::Object(new_map)) => {
+                    let child_outer = line_indent(self.bytes, member.start).unwrap_or_else(|| inner.clone());
+                    self.update_object(child, old_map, new_map, &child_outer);
+                },
+                _ => {
+                    let text = self.render(value, &inner);
+                    self.edits.push(Edit { start: member.value_start, end: member.value_end, text });
+                },
+            }
+        }
+
+        let is_last = node.members.last().map(|m| std::ptr::eq(m, last)).unwrap_or(false);
+        if let (false, Some(comma)) = (is_last, last.comma) {
+            self.edits.push(Edit { start: comma, end: comma + 1, text: String::new() });
+        }
+
+        if !added.is_empty() {
+            let members = self.render_members(&added, &inner, ",\n");
+            match self.line_end(last.value_end) {
+                // Keep a comment trailing the last member on its line.
+                Some(end) if end > last.value_end => {
+                    self.edits.push(Edit { start: last.value_end, end: last.value_end, text: ",".to_string() });
+                    self.edits.push(Edit { start: end, end, text: format!("\n{}", members) });
+                },
+                _ => {
+                    let text = format!(",\n{}", members);
+                    self.edits.push(Edit { start: last.value_end, end: last.value_end, text });
+                },
+            }
+        }
+    }
+
+    /// End of the line at `pos`, if nothing but whitespace, commas or
+    /// comments (blanked in `bytes`) follow on it.
+    fn line_end(&self, pos: usize) -> Option<usize> {
+        let nl = self.bytes[pos..].iter().position(|b| *b == b'\n')?;
+        match self.bytes[pos..pos + nl].iter().all(|b| b.is_ascii_whitespace() || *b == b',') {
+            true => Some(pos + nl),
+            false => None,
+        }
+    }
+
+    /// Single line objects are edited in place with compact values.
+    fn update_inline_object(&mut self, node: &ObjectNode, old: &Map<String, Value>, new: &Map<String, Value>) {
+        let added: Vec<String> = new.iter()
+            .filter(|(key, _)| !node.members.iter().any(|m| &m.key == *key))
+            .map(|(key, value)| format!("{}: {}", Value::String(key.to_string()), value))
+            .collect();
+
+        let last = match node.members.iter().rev().find(|m| new.contains_key(&m.key)) {
+            Some(last) => last,
+            None => {
+                let text = match added.is_empty() {
+                    true => String::new(),
+                    false => format!(" {} ", added.join(", ")),
+                };
+                self.edits.push(Edit { start: node.open + 1, end: node.close, text });
+                return;
+            }
+        };
+
+        for member in &node.members {
+            match new.get(&member.key) {
+                None => self.remove_member(member),
+                Some(value) if old.get(&member.key) != Some(value) => {
+                    let text = value.to_string();
+                    self.edits.push(Edit { start: member.value_start, end: member.value_end, text });
+                },
+                Some(_) => (),
+            }
+        }
+
+        let is_last = node.members.last().map(|m| std::ptr::eq(m, last)).unwrap_or(false);
+        if let (false, Some(comma)) = (is_last, last.comma) {
+            self.edits.push(Edit { start: comma, end: comma + 1, text: String::new() });
+        }
+
+        if !added.is_empty() {
+            let text = format!(", {}", added.join(", "));
+            self.edits.push(Edit { start: last.value_end, end: last.value_end, text });
+        }
+    }
+
+    fn remove_member(&mut self, member: &Member) {
+        let own_line = line_indent(self.bytes, member.start).is_some();
+        let start = match own_line {
+            true => line_start(self.bytes, member.start),
+            false => member.start,
+        };
+
+        let mut end = member.comma.map(|c| c + 1).unwrap_or(member.value_end);
+        if !own_line && member.comma.is_some() {
+            while end < self.bytes.len() && self.bytes[end].is_ascii_whitespace() {
+                end += 1;
+            }
+        } else if own_line {
+            let rest = self.bytes[end..].iter().position(|b| *b == b'\n');
+            if let Some(nl) = rest {
+                if self.bytes[end..end + nl].iter().all(|b| b.is_ascii_whitespace()) {
+                    end += nl + 1;
+                }
+            }
+        }
+
+        self.edits.push(Edit { start, end, text: String::new() });
+    }
+
+    fn render_members(&self, members: &[(&String, &Value)], indent: &str, separator: &str) -> String {
+        members.iter()
+            .map(|(key, value)| format!("{}{}: {}", indent, Value::String(key.to_string()), self.render(value, indent)))
+            .collect::<Vec<String>>()
+            .join(separator)
+    }
+
+    fn render(&self, value: &Value, indent: &str) -> String {
+        let mut buf = Vec::new();
+        let formatter = serde_json::ser::PrettyFormatter::with_indent(self.unit.as_bytes());
+        let mut ser = serde_json::Serializer::with_formatter(&mut buf, formatter);
+        match serde::Serialize::serialize(value, &mut ser) {
+            Ok(_) => String::from_utf8(buf).unwrap_or_default().replace('\n', &format!("\n{}", indent)),
+            Err(_) => value.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn strip_comments_keeps_offsets() {
+        let text = "{\n  // note\n  \"a\": 1 /* x */\n}";
+        let stripped = strip_comments(text);
+        assert_eq!(stripped.len(), text.len());
+        assert_eq!(serde_json::from_str::<Value>(&stripped).unwrap(), json!({ "a": 1 }));
+    }
+
+    #[test]
+    fn strip_comments_ignores_strings() {
+        let text = r#"{ "url": "http://host/*x*/" }"#;
+        assert_eq!(strip_comments(text), text);
+    }
+
+    #[test]
+    fn strip_comments_blanks_trailing_commas() {
+        let text = "{ \"a\": [1, 2,], }";
+        assert_eq!(serde_json::from_str::<Value>(&strip_comments(text)).unwrap(), json!({ "a": [1, 2] }));
+    }
+
+    #[test]
+    fn update_replaces_changed_value_only() {
+        let text = "{\n  \"b\": 1, // keep\n  \"a\": 2\n}";
+        let result = update(text, &json!({ "a": 2, "b": 3 })).unwrap();
+        assert_eq!(result, "{\n  \"b\": 3, // keep\n  \"a\": 2\n}");
+    }
+
+    #[test]
+    fn update_adds_nested_key_after_last_member() {
+        let text = "{\n    \"z\": 1\n}";
+        let result = update(text, &json!({ "z": 1, "core": { "repo": { "path": "p" } } })).unwrap();
+        assert_eq!(result, "{\n    \"z\": 1,\n    \"core\": {\n        \"repo\": {\n            \"path\": \"p\"\n        }\n    }\n}");
+    }
+
+    #[test]
+    fn update_adds_after_trailing_comment() {
+        let text = "{\n  \"a\": 1 // note\n}";
+        let result = update(text, &json!({ "a": 1, "b": 2 })).unwrap();
+        assert_eq!(result, "{\n  \"a\": 1, // note\n  \"b\": 2\n}");
+
+        let text = "{\n  \"a\": 1, // note\n  \"c\": 3\n}";
+        let result = update(text, &json!({ "a": 1, "b": 2 })).unwrap();
+        assert_eq!(result, "{\n  \"a\": 1, // note\n  \"b\": 2\n}");
+    }
+
+    #[test]
+    fn update_adds_into_existing_object() {
+        let text = "{\n  \"core\": {\n    \"git\": \"x\"\n  }\n}";
+        let result = update(text, &json!({ "core": { "git": "x", "log": "info" } })).unwrap();
+        assert_eq!(result, "{\n  \"core\": {\n    \"git\": \"x\",\n    \"log\": \"info\"\n  }\n}");
+    }
+
+    #[test]
+    fn update_fills_empty_object() {
+        let result = update("{}", &json!({ "a": 1 })).unwrap();
+        assert_eq!(result, "{\n  \"a\": 1\n}");
+    }
+
+    #[test]
+    fn update_removes_last_member_and_comma() {
+        let text = "{\n  \"a\": 1,\n  \"b\": 2 // gone\n}";
+        let result = update(text, &json!({ "a": 1 })).unwrap();
+        assert_eq!(result, "{\n  \"a\": 1\n}");
+    }
+
+    #[test]
+    fn update_removes_middle_member() {
+        let text = "{\n  \"a\": 1,\n  \"b\": 2,\n  \"c\": 3\n}";
+        let result = update(text, &json!({ "a": 1, "c": 3 })).unwrap();
+        assert_eq!(result, "{\n  \"a\": 1,\n  \"c\": 3\n}");
+    }
+
+    #[test]
+    fn update_empties_object() {
+        let text = "{\n  \"a\": 1\n}";
+        assert_eq!(update(text, &json!({})).unwrap(), "{}");
+    }
+
+    #[test]
+    fn update_edits_inline_object_in_place() {
+        let text = "{\n  \"core\": { \"git\": 1, \"log\": 2 }\n}";
+        let result = update(text, &json!({ "core": { "log": 3, "repo": { "path": "p" } } })).unwrap();
+        assert_eq!(result, "{\n  \"core\": { \"log\": 3, \"repo\": {\"path\":\"p\"} }\n}");
+    }
+
+    #[test]
+    fn update_replaces_every_member_of_inline_object() {
+        let text = r#"{"core":{"git":{"user":"me"}},"x":1}"#;
+        assert_eq!(update(text, &json!({ "y": 2 })).unwrap(), r#"{ "y": 2 }"#);
+        assert_eq!(update(text, &json!({})).unwrap(), "{}");
+    }
+
+    #[test]
+    fn update_fails_for_non_object() {
+        assert_eq!(update("[]", &json!({})), None);
+    }
+}
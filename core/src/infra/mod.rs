@@ -1,4 +1,11 @@
+pub mod app_dirs;
 pub mod enum_str;
 pub mod error;
+pub mod host;
 pub mod io;
-pub mod priority_provider;
\ No newline at end of file
+pub mod jsonc;
+pub mod priority_provider;
+pub mod wildcard;
+
+#[cfg(test)]
+pub mod testing;
@@ -30,12 +30,9 @@ pub struct PriorityProviderIterator<'a, T> {
 
 impl<'a, T> PriorityProviderIterator<'a, T> {
     pub fn new(src: &'a PriorityProvider<T>) -> PriorityProviderIterator<'a, T> {
-        let mut sorted_keys: Vec<i64> = src.priority.iter().map(|&p| p).collect();
-        sorted_keys.sort();
-
         PriorityProviderIterator {
             providers: &src.providers,
-            sorted_keys,
+            sorted_keys: src.priorities(),
             index: 0,
         }
     }
@@ -59,6 +56,12 @@ impl<'a, T> Iterator for PriorityProviderIterator<'a, T> {
     }
 }
 
+impl<T> Default for PriorityProvider<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T> PriorityProvider<T> {
     pub fn new() -> PriorityProvider<T> {
         PriorityProvider {
@@ -78,7 +81,7 @@ impl<T> PriorityProvider<T> {
     }
 
     /// Returns an iterator for the providers.
-    pub fn iter(&self) -> PriorityProviderIterator<T> {
+    pub fn iter(&self) -> PriorityProviderIterator<'_, T> {
         PriorityProviderIterator::new(self)
     }
 
@@ -105,33 +108,34 @@ impl<T> PriorityProvider<T> {
         }
     }
     
-    /// Adds a provider to the end of the list.
-    pub fn add(&mut self, provider: T) {
+    /// Returns the positions in use, sorted from top to bottom.
+    pub fn priorities(&self) -> Vec<i64> {
+        let mut sorted_keys: Vec<i64> = self.priority.iter().copied().collect();
+        sorted_keys.sort();
+        sorted_keys
+    }
+    
+    /// Adds a provider to the end of the list and returns its position.
+    pub fn add(&mut self, provider: T) -> i64 {
         let pos = self.upper_priority + 1;
         self.set_at(pos, provider);
+        pos
     }
 
-    /// Adds a provider to the top of the list.
-    pub fn add_top(&mut self, provider: T) {
+    /// Adds a provider to the top of the list and returns its position.
+    pub fn add_top(&mut self, provider: T) -> i64 {
         let pos = self.lowest_priority - 1;
         self.set_at(pos, provider);
+        pos
     }
 
     /// Returns the first provider that matches the given filter.
     pub fn first(&self, filter: &dyn Fn(&T) -> bool) -> Option<&T> {
-        for provider in self.iter() {
-            if filter(provider) {
-                return Some(provider);
-            }
-        }
-
-        None
+        self.iter().find(|provider| filter(provider))
     }
 
     pub fn first_mut(&mut self, filter: &dyn Fn(&T) -> bool) -> Option<&mut T> {
-        let mut sorted_keys: Vec<i64> = self.priority.iter().map(|&p| p).collect();
-        sorted_keys.sort();
-        for key in sorted_keys {
+        for key in self.priorities() {
             if filter(self.providers.get(&key).unwrap()) {
                 return self.providers.get_mut(&key);
             }
@@ -152,9 +156,7 @@ impl<T> PriorityProvider<T> {
     }
 
     pub fn map_first_mut<U>(&mut self, filter: &dyn Fn(&T) -> Option<U>) -> Option<U> {
-        let mut sorted_keys: Vec<i64> = self.priority.iter().map(|&p| p).collect();
-        sorted_keys.sort();
-        for key in sorted_keys {
+        for key in self.priorities() {
             match filter(self.providers.get(&key).unwrap()) {
                 Some(value) => return Some(value),
                 None => continue,
@@ -166,9 +168,7 @@ impl<T> PriorityProvider<T> {
 
     pub fn map_mut<U>(&mut self, filter: &dyn Fn(&mut T) -> Option<U>) -> Vec<U> {
         let mut result = Vec::new();
-        let mut sorted_keys: Vec<i64> = self.priority.iter().map(|&p| p).collect();
-        sorted_keys.sort();
-        for key in sorted_keys {
+        for key in self.priorities() {
             match filter(self.providers.get_mut(&key).unwrap()) {
                 Some(value) => result.push(value),
                 None => continue,
@@ -179,9 +179,7 @@ impl<T> PriorityProvider<T> {
     }
 
     pub fn each_mut(&mut self, filter: &dyn Fn(&mut T)) {
-        let mut sorted_keys: Vec<i64> = self.priority.iter().map(|&p| p).collect();
-        sorted_keys.sort();
-        for key in sorted_keys {
+        for key in self.priorities() {
             filter(self.providers.get_mut(&key).unwrap());
         }
     }
@@ -247,6 +245,16 @@ mod tests {
         assert_eq!(src.get_at(-2), Some(&"2"));
     }
 
+    #[test]
+    pub fn priorities_returns_sorted_positions() {
+        let mut src = PriorityProvider::new();
+        src.add("1");
+        src.add_top("2");
+        src.set_at(5, "3");
+
+        assert_eq!(src.priorities(), vec![-1, 1, 5]);
+    }
+
     #[test]
     pub fn first_returns_first_provider_that_matches_filter() {
         let mut src = PriorityProvider::new();
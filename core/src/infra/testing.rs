@@ -0,0 +1,14 @@
+//! Fixtures shared by the unit tests.
+
+use std::fs;
+use std::path::PathBuf;
+
+/// Empty folder under the system temp dir named `sh-<name>-<pid>`, left
+/// over content from an earlier run is removed first. Names must be
+/// unique across the crate as tests run in parallel.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sh-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
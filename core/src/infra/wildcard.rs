@@ -0,0 +1,72 @@
+/// Whether the text has wildcard characters.
+pub fn is_pattern(text: &str) -> bool {
+    text.contains(['*', '?'])
+}
+
+/// Matches text against a pattern where `*` stands for any run of
+/// characters (dots included) and `?` for exactly one character.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            },
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            },
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                },
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_literal_text() {
+        assert!(matches("core.git.user", "core.git.user"));
+        assert!(!matches("core.git.user", "core.git.email"));
+        assert!(!matches("core.git", "core.git.user"));
+    }
+
+    #[test]
+    fn matches_star_across_dots() {
+        assert!(matches("core.git.*", "core.git.user"));
+        assert!(matches("core.*", "core.git.user"));
+        assert!(matches("*.user", "core.git.user"));
+        assert!(matches("core.*.user", "core.git.user"));
+        assert!(!matches("core.git.*", "core.gitx"));
+        assert!(matches("*", ""));
+    }
+
+    #[test]
+    fn matches_question_mark() {
+        assert!(matches("host-?", "host-1"));
+        assert!(!matches("host-?", "host-10"));
+    }
+
+    #[test]
+    fn is_pattern_detects_wildcards() {
+        assert!(is_pattern("core.*"));
+        assert!(is_pattern("host-?"));
+        assert!(!is_pattern("core.git.user"));
+    }
+}